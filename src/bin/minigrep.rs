@@ -0,0 +1,86 @@
+// minigrep 的命令行入口, 逻辑都在 lib.rs 里
+use std::{env, process};
+use the_rust_programming_language::{Command, MinigrepError};
+
+// 和 grep 一样: 0 有匹配, 1 没有匹配, 出错时按错误的种类区分
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_PATTERN: i32 = 4;
+const EXIT_ENCODING: i32 = 5;
+
+fn exit_code(err: &MinigrepError) -> i32 {
+    match err {
+        MinigrepError::Usage(_) => EXIT_USAGE,
+        MinigrepError::Io { .. } => EXIT_IO,
+        MinigrepError::Pattern(_) => EXIT_PATTERN,
+        MinigrepError::Encoding { .. } => EXIT_ENCODING,
+        // 部分文件失败时按第一个失败的原因
+        MinigrepError::Partial { first, .. } => exit_code(first),
+    }
+}
+
+fn main() {
+    let config = match Command::parse(env::args()) {
+        Ok(Command::Search(config)) => config,
+        Ok(Command::Help) => {
+            print!("{}", Command::help());
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("{}", Command::version());
+            return;
+        }
+        Ok(Command::DebugConfig(dump)) => {
+            print!("{}", dump);
+            return;
+        }
+        Ok(Command::BuildIndex(dir)) => {
+            let mut skipped = Vec::new();
+            let built = the_rust_programming_language::build_index(&dir, &mut skipped);
+            // 读不了的目录不影响其他文件进索引, 报告之后以错误退出
+            for e in &skipped {
+                eprintln!("minigrep: {}", e);
+            }
+            match built {
+                Ok(stats) => {
+                    println!(
+                        "indexed {} files in {} ({} updated, {} removed)",
+                        stats.files,
+                        dir.display(),
+                        stats.updated,
+                        stats.removed
+                    );
+                    match skipped.first() {
+                        Some(e) => process::exit(exit_code(e)),
+                        None => return,
+                    }
+                }
+                Err(e) => {
+                    eprintln!("minigrep: {}", e);
+                    process::exit(exit_code(&e));
+                }
+            }
+        }
+        Err(err @ MinigrepError::Usage(_)) => {
+            // eprintln! 宏来打印到标准错误流 否则如果输出到文件的话,会将错误信息输出到文件
+            eprintln!("problem parsing arguments: {}", err);
+            process::exit(exit_code(&err))
+        }
+        // 比如 -f 指定的模式文件读不了
+        Err(err) => {
+            eprintln!("minigrep: {}", err);
+            process::exit(exit_code(&err))
+        }
+    };
+
+    match the_rust_programming_language::run(config) {
+        Ok(true) => process::exit(EXIT_MATCH),
+        Ok(false) => process::exit(EXIT_NO_MATCH),
+        Err(e) => {
+            eprintln!("Application error : {}", e);
+            process::exit(exit_code(&e));
+        }
+    }
+}
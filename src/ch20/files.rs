@@ -0,0 +1,155 @@
+use super::Response;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 按扩展名 (小写) 决定 Content-Type, 不认识的当作任意二进制数据
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("rs", "text/plain; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+];
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    MIME_TYPES
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map_or("application/octet-stream", |(_, mime)| mime)
+}
+
+/// 把请求里的路径 (已经解码过 %XX) 对应到 root 下面的文件
+///
+/// 出现 `..` 和隐藏文件 (`.git`, `.env` 之类) 的路径直接拒绝, 返回 None.
+/// 单独的 `.` 和多余的 `/` 被忽略
+pub fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in url_path.split('/') {
+        match segment {
+            "" | "." => {}
+            s if s.starts_with('.') => return None,
+            // 在 Windows 上 `\` 和 `C:` 也能跳出 root
+            s if s.contains(['\\', ':', '\0']) => return None,
+            s => path.push(s),
+        }
+    }
+    Some(path)
+}
+
+/// GET url_path 的响应: 文件内容, 目录下的 index.html, 或者 403 / 404
+///
+/// resolve 拒绝的路径回 404, 不告诉对方隐藏文件在不在; 符号链接指到 root 外面的算 403
+pub fn serve_file(root: &Path, url_path: &str) -> Response {
+    let path = match resolve(root, url_path) {
+        Some(path) => path,
+        None => return error_page(root, 404, "NOT FOUND"),
+    };
+    let path = if path.is_dir() {
+        path.join("index.html")
+    } else {
+        path
+    };
+    let inside_root = match (root.canonicalize(), path.canonicalize()) {
+        (Ok(root), Ok(path)) => path.starts_with(root),
+        (_, Err(e)) => return io_error_page(root, &e),
+        (Err(e), _) => return io_error_page(root, &e),
+    };
+    if !inside_root {
+        return error_page(root, 403, "Forbidden");
+    }
+    // 按字节读, 图片之类的二进制文件也能原样发出去
+    match fs::read(&path) {
+        Ok(contents) => Response::new(200, "OK")
+            .header("Content-Type", mime_type(&path))
+            .body(contents),
+        Err(e) => io_error_page(root, &e),
+    }
+}
+
+fn io_error_page(root: &Path, e: &io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::NotFound => error_page(root, 404, "NOT FOUND"),
+        io::ErrorKind::PermissionDenied => error_page(root, 403, "Forbidden"),
+        _ => {
+            eprintln!("static file error: {}", e);
+            error_page(root, 500, "Internal Server Error")
+        }
+    }
+}
+
+/// 404 时用 root 下的 404.html, 没有这个文件或者是别的状态码就只回一行文字
+pub fn error_page(root: &Path, status: u16, reason: &'static str) -> Response {
+    let response = Response::new(status, reason);
+    if status == 404 {
+        if let Ok(contents) = fs::read(root.join("404.html")) {
+            return response
+                .header("Content-Type", mime_type(Path::new("404.html")))
+                .body(contents);
+        }
+    }
+    response
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(format!("{} {}\n", status, reason).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn resolves_paths_inside_root() {
+        let root = Path::new("/srv/www");
+        assert_eq!(
+            Some(root.join("css").join("site.css")),
+            resolve(root, "/css/./site.css")
+        );
+        assert_eq!(Some(root.to_path_buf()), resolve(root, "//"));
+        assert_eq!(None, resolve(root, "/css/../../etc/passwd"));
+        assert_eq!(None, resolve(root, "/..\\secret"));
+        assert_eq!(None, resolve(root, "/.git/config"));
+        assert_eq!(None, resolve(root, "/docs/.env"));
+        assert_eq!("image/png", mime_type(Path::new("logo.PNG")));
+        assert_eq!("application/octet-stream", mime_type(Path::new("data")));
+    }
+
+    #[test]
+    fn serves_files_and_index() {
+        let root = env::temp_dir().join(format!("ch20-files-{}", process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs").join("index.html"), "<h1>docs</h1>").unwrap();
+        fs::write(root.join("blob.bin"), [0u8, 159, 146, 150]).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".git").join("config"), "[core]").unwrap();
+
+        let index = serve_file(&root, "/docs/");
+        assert_eq!((200, b"<h1>docs</h1>".to_vec()), (index.status, index.body));
+        let blob = serve_file(&root, "/blob.bin");
+        assert_eq!(vec![0u8, 159, 146, 150], blob.body);
+        assert_eq!(404, serve_file(&root, "/missing.txt").status);
+        assert_eq!(404, serve_file(&root, "/docs/../../etc/passwd").status);
+        assert_eq!(404, serve_file(&root, "/.git/config").status);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
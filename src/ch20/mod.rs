@@ -0,0 +1,421 @@
+mod files;
+mod request;
+
+use request::{is_timeout, Limits, ParseError, Request, RequestReader};
+use std::env;
+use std::io::prelude::*;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{io, thread};
+use threadPool::ThreadPool;
+
+/// 服务器的设置, main 里的默认值可以用 CH20_ 开头的环境变量覆盖, 见 from_env
+#[derive(Clone)]
+pub struct Config {
+    pub addr: String,
+    // 静态文件的根目录, URL 路径都对应到它下面. 只放要公开的文件, 不要指向项目目录
+    pub root: PathBuf,
+    // 处理连接的线程数
+    pub workers: usize,
+    // 所有线程都在忙时最多有几个连接排队, 再多的直接回 503
+    pub queue: usize,
+    // 长连接上两个请求之间最多等多久, 超过就关掉连接把线程让出来
+    pub idle_timeout: Duration,
+    // 从收到请求的第一个字节起, 整个请求最多读多久, 超过回 408.
+    // 只限制每次 read 的话, 隔几秒发一个字节的客户端能一直占着线程
+    pub request_timeout: Duration,
+    // 一个连接最多处理几个请求, 到了就在响应里带上 Connection: close
+    pub max_requests: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            addr: "127.0.0.1:7878".to_string(),
+            root: PathBuf::from("public"),
+            workers: 4,
+            queue: 16,
+            idle_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            max_requests: 100,
+        }
+    }
+}
+
+impl Config {
+    /// CH20_WORKERS, CH20_QUEUE, CH20_ROOT, CH20_IDLE_TIMEOUT, CH20_REQUEST_TIMEOUT (秒)
+    /// 和 CH20_MAX_REQUESTS
+    pub fn from_env() -> Config {
+        let number = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(default)
+        };
+        let default = Config::default();
+        Config {
+            workers: number("CH20_WORKERS", default.workers),
+            queue: number("CH20_QUEUE", default.queue),
+            root: env::var_os("CH20_ROOT").map_or(default.root.clone(), PathBuf::from),
+            idle_timeout: Duration::from_secs(number(
+                "CH20_IDLE_TIMEOUT",
+                default.idle_timeout.as_secs() as usize,
+            ) as u64),
+            request_timeout: Duration::from_secs(number(
+                "CH20_REQUEST_TIMEOUT",
+                default.request_timeout.as_secs() as usize,
+            ) as u64),
+            max_requests: number("CH20_MAX_REQUESTS", default.max_requests),
+            ..default
+        }
+    }
+}
+
+pub fn main() {
+    if let Err(e) = serve(&Config::from_env()) {
+        eprintln!("server error: {}", e);
+    }
+}
+
+// 以前每个连接都 thread::spawn 一个线程, 一堆 /sleep 请求就能把线程耗光.
+// 现在连接交给固定大小的线程池, 排队也满了就在接受连接的线程上直接回 503
+pub fn serve(config: &Config) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.addr)?;
+    let pool = ThreadPool::with_queue(config.workers, config.queue);
+    let load = pool.load();
+    let shared = Arc::new(config.clone());
+
+    for stream in listener.incoming() {
+        // 单个连接出错 (比如对方已经断开) 不影响继续接受别的连接
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+
+        // 闭包拿走了 stream, 被退回时要用另一个句柄回 503
+        let busy = match stream.try_clone() {
+            Ok(busy) => busy,
+            Err(e) => {
+                eprintln!("connection failed: {}", e);
+                continue;
+            }
+        };
+        let config = Arc::clone(&shared);
+        let load = load.clone();
+        let handle = move || {
+            if let Err(e) = handle_connection(stream, &config, || load.queued() > 0) {
+                eprintln!("connection failed: {}", e);
+            }
+        };
+        if pool.try_execute(handle).is_err() {
+            // 客户端收没收到都不重要, 连接马上就关了
+            let _ = service_unavailable(busy);
+        }
+    }
+    Ok(())
+}
+
+fn service_unavailable(mut stream: TcpStream) -> io::Result<()> {
+    let body = "Server is busy, please try again later.\n";
+    Response::new(503, "Service Unavailable")
+        .header("Retry-After", "1")
+        .body(body.as_bytes().to_vec())
+        .write_to(&mut stream, false)
+}
+
+/// 要写回给客户端的响应, Content-Length 和 Connection 由 write_to 加上
+pub struct Response {
+    pub status: u16,
+    pub reason: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, reason: &'static str) -> Response {
+        Response {
+            status,
+            reason,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Response {
+        self.body = body;
+        self
+    }
+
+    /// keep_alive 为 false 时告诉客户端这是连接上的最后一个响应
+    pub fn write_to<W: Write>(&self, out: &mut W, keep_alive: bool) -> io::Result<()> {
+        // 状态行和响应头先拼好, 和响应体一起只写一次
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: {}\r\n\r\n",
+            self.body.len(),
+            if keep_alive { "keep-alive" } else { "close" }
+        ));
+        let mut response = head.into_bytes();
+        response.extend_from_slice(&self.body);
+        out.write_all(&response)?;
+        out.flush()
+    }
+}
+
+// 空闲时每隔多久看一次有没有别的连接在排队
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
+// 读连接时按 Config 的两个超时设置 read 的超时, 超时的 read 返回 TimedOut:
+// 还没收到下一个请求时最多等 idle_timeout, 有别的连接在排队 (others_waiting) 时提前放弃;
+// 收到第一个字节之后整个请求要在 request_timeout 内读完, 每次 read 的超时是剩下的时间
+struct TimedStream<'a, F> {
+    stream: &'a TcpStream,
+    config: &'a Config,
+    others_waiting: F,
+    // 当前请求必须在这之前读完, 还没收到它的字节时是 None
+    deadline: Option<Instant>,
+}
+
+impl<F: Fn() -> bool> TimedStream<'_, F> {
+    // 一个请求读完了, 下一个请求的时间从它的第一个字节算起
+    fn next_request(&mut self) {
+        self.deadline = None;
+    }
+
+    fn idle_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let idle_until = Instant::now() + self.config.idle_timeout;
+        loop {
+            let left = idle_until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.set_read_timeout(Some(left.min(IDLE_POLL)))?;
+            match (&mut &*self.stream).read(buf) {
+                Err(e) if is_timeout(&e) && (self.others_waiting)() => return Err(e),
+                Err(e) if is_timeout(&e) => {}
+                Ok(n) => {
+                    if n > 0 {
+                        self.deadline = Some(Instant::now() + self.config.request_timeout);
+                    }
+                    return Ok(n);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<F: Fn() -> bool> Read for TimedStream<'_, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return self.idle_read(buf),
+        };
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        (&mut &*self.stream).read(buf)
+    }
+}
+
+// 一个连接上按顺序处理请求, 直到有一方要求关闭, 超时, 或者达到 max_requests.
+// 流水线里客户端不等响应就发来的请求留在 RequestReader 的缓冲区里, 响应的顺序和请求一致.
+// 请求格式不对时按 ParseError 回 400 / 408 / 413 / 431 然后关闭, 因为已经找不到下一个请求从哪开始了
+fn handle_connection<F>(stream: TcpStream, config: &Config, others_waiting: F) -> io::Result<()>
+where
+    F: Fn() -> bool,
+{
+    let timed = TimedStream {
+        stream: &stream,
+        config,
+        others_waiting,
+        deadline: None,
+    };
+    let mut requests = RequestReader::new(timed, Limits::default());
+    for served in 1.. {
+        requests.get_mut().next_request();
+        let (response, keep_alive) = match requests.next_request() {
+            Ok(Some(request)) => {
+                let keep_alive = wants_keep_alive(&request) && served < config.max_requests;
+                (route(&request, &config.root), keep_alive)
+            }
+            Ok(None) => return Ok(()),
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                let (status, reason) = e.status().unwrap_or((400, "Bad Request"));
+                let response = Response::new(status, reason).body(format!("{}\n", e).into_bytes());
+                (response, false)
+            }
+        };
+        response.write_to(&mut &stream, keep_alive)?;
+        if !keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// HTTP/1.1 默认是长连接, 除非带了 Connection: close; HTTP/1.0 要明确说 keep-alive
+fn wants_keep_alive(request: &Request) -> bool {
+    let has_token = |token: &str| {
+        request.header("connection").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if request.version == "HTTP/1.0" {
+        has_token("keep-alive")
+    } else {
+        !has_token("close")
+    }
+}
+
+// 书里的 / 和 /sleep 还是 hello.html, 其他路径都是 root 下的静态文件
+fn route(request: &Request, root: &Path) -> Response {
+    if request.method != "GET" {
+        return files::error_page(root, 405, "Method Not Allowed").header("Allow", "GET");
+    }
+    match request.path.as_str() {
+        "/" => files::serve_file(root, "/hello.html"),
+        "/sleep" => {
+            thread::sleep(Duration::from_secs(5));
+            files::serve_file(root, "/hello.html")
+        }
+        path => files::serve_file(root, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    #[test]
+    fn keep_alive_rules() {
+        let request = |version: &str, connection: Option<&str>| {
+            let mut head = format!("GET / {}\r\n", version);
+            if let Some(connection) = connection {
+                head.push_str(&format!("Connection: {}\r\n", connection));
+            }
+            head.push_str("\r\n");
+            RequestReader::new(head.as_bytes(), Limits::default())
+                .next_request()
+                .unwrap()
+                .unwrap()
+        };
+        assert!(wants_keep_alive(&request("HTTP/1.1", None)));
+        assert!(!wants_keep_alive(&request(
+            "HTTP/1.1",
+            Some("Upgrade, Close")
+        )));
+        assert!(!wants_keep_alive(&request("HTTP/1.0", None)));
+        assert!(wants_keep_alive(&request("HTTP/1.0", Some("keep-alive"))));
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            root: Path::new(env!("CARGO_MANIFEST_DIR")).join("public"),
+            max_requests: 3,
+            ..Config::default()
+        };
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &config, || false).unwrap();
+        });
+
+        // 四个请求一次发完, 第三个之后达到 max_requests, 第四个不会被处理
+        let mut client = TcpStream::connect(addr).unwrap();
+        let request = |path: &str| format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let pipelined: String = ["/hello.html", "/missing", "/404.html", "/hello.html"]
+            .iter()
+            .map(|path| request(path))
+            .collect();
+        client.write_all(pipelined.as_bytes()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        server.join().unwrap();
+
+        let status_lines: Vec<&str> = responses
+            .lines()
+            .filter(|line| line.starts_with("HTTP/1.1"))
+            .collect();
+        assert_eq!(
+            vec![
+                "HTTP/1.1 200 OK",
+                "HTTP/1.1 404 NOT FOUND",
+                "HTTP/1.1 200 OK"
+            ],
+            status_lines
+        );
+        assert_eq!(2, responses.matches("Connection: keep-alive").count());
+        assert!(responses.contains("Connection: close"));
+    }
+
+    #[test]
+    fn slow_client_gets_408() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            idle_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_millis(300),
+            ..Config::default()
+        };
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let start = Instant::now();
+            handle_connection(stream, &config, || false).unwrap();
+            start.elapsed()
+        });
+
+        // 每 100ms 发一个字节, 每次 read 都不会超时, 但整个请求超过了 request_timeout
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut response = Vec::new();
+        for byte in b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Slow: "
+            .iter()
+            .cycle()
+        {
+            client.write_all(&[*byte]).unwrap();
+            let mut buf = [0; 1024];
+            match client.read(&mut buf) {
+                Ok(n) => {
+                    response.extend_from_slice(&buf[..n]);
+                    break;
+                }
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+        client.set_read_timeout(None).unwrap();
+        client.read_to_end(&mut response).unwrap();
+        let elapsed = server.join().unwrap();
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(elapsed < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::str;
+
+/// 请求头和请求体的大小上限, 超过的请求分别回 431 和 413
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// 请求行加上所有请求头, 包括最后的空行
+    pub max_head: usize,
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_head: 8 * 1024,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+/// 解析好的一个请求
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// 解码过 %XX 的路径, 不含查询字符串
+    pub path: String,
+    /// 查询参数按出现的顺序, 同名的参数可以有多个
+    pub query: Vec<(String, String)>,
+    /// 比如 HTTP/1.1
+    pub version: String,
+    /// 名字都转成小写, 重复的请求头用 ", " 连起来
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// 名字不区分大小写
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// 第一个叫 name 的查询参数
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 读请求时出的错, 除了 Io 都要给客户端回一个对应的状态码
+#[derive(Debug)]
+pub enum ParseError {
+    /// 400, 带着哪里不对
+    BadRequest(&'static str),
+    /// 413 请求体超过 Limits::max_body
+    PayloadTooLarge,
+    /// 431 请求头超过 Limits::max_head
+    HeadersTooLarge,
+    /// 408 请求发到一半, 超过读超时还没有下文
+    Timeout,
+    /// 读连接出错, 或者请求还没发完连接就断了, 这时没法再回复
+    Io(io::Error),
+}
+
+impl ParseError {
+    /// 回给客户端的状态码和原因短语, Io 错误时没有
+    pub fn status(&self) -> Option<(u16, &'static str)> {
+        match self {
+            ParseError::BadRequest(_) => Some((400, "Bad Request")),
+            ParseError::PayloadTooLarge => Some((413, "Payload Too Large")),
+            ParseError::HeadersTooLarge => Some((431, "Request Header Fields Too Large")),
+            ParseError::Timeout => Some((408, "Request Timeout")),
+            ParseError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            ParseError::PayloadTooLarge => write!(f, "request body is too large"),
+            ParseError::HeadersTooLarge => write!(f, "request header fields are too large"),
+            ParseError::Timeout => write!(f, "timed out waiting for the rest of the request"),
+            ParseError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+/// 从连接里一个接一个地读请求
+///
+/// 一次 read 可能只拿到半个请求, 也可能拿到不止一个, 多读的部分留在 buf 里给下一个请求
+pub struct RequestReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    limits: Limits,
+}
+
+impl<R: Read> RequestReader<R> {
+    pub fn new(reader: R, limits: Limits) -> RequestReader<R> {
+        RequestReader {
+            reader,
+            buf: Vec::new(),
+            limits,
+        }
+    }
+
+    /// 连接在两个请求之间正常关闭, 或者空闲到读超时时返回 None
+    ///
+    /// reader 设置了读超时 (比如 TcpStream::set_read_timeout) 时, 请求读到一半超时是 Timeout
+    pub fn next_request(&mut self) -> Result<Option<Request>, ParseError> {
+        let head_end = loop {
+            if let Some(end) = find_head_end(&self.buf) {
+                break end;
+            }
+            if self.buf.len() >= self.limits.max_head {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            match self.fill() {
+                Ok(0) | Err(ParseError::Timeout) if self.buf.is_empty() => return Ok(None),
+                Ok(0) => return Err(unexpected_eof()),
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+        };
+        if head_end > self.limits.max_head {
+            return Err(ParseError::HeadersTooLarge);
+        }
+
+        let head = str::from_utf8(&self.buf[..head_end])
+            .map_err(|_| ParseError::BadRequest("request head is not valid UTF-8"))?;
+        let mut request = parse_head(head)?;
+        let length = body_length(&request, self.limits)?;
+
+        let body_start = head_end;
+        while self.buf.len() < body_start + length {
+            if self.fill()? == 0 {
+                return Err(unexpected_eof());
+            }
+        }
+        request.body = self.buf[body_start..body_start + length].to_vec();
+        self.buf.drain(..body_start + length);
+        Ok(Some(request))
+    }
+
+    /// 底层的 reader, 用来在两个请求之间调整它的设置
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn fill(&mut self) -> Result<usize, ParseError> {
+        let mut chunk = [0; 4096];
+        let n = loop {
+            match self.reader.read(&mut chunk) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if is_timeout(&e) => return Err(ParseError::Timeout),
+                result => break result?,
+            }
+        };
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+}
+
+/// 读超时的错误, 不同平台上分别报这两种
+pub fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+fn unexpected_eof() -> ParseError {
+    ParseError::Io(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "connection closed in the middle of a request",
+    ))
+}
+
+// 空行之后的位置, 也就是请求体开始的地方
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|i| i + 4)
+}
+
+fn parse_head(head: &str) -> Result<Request, ParseError> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(ParseError::BadRequest("malformed request line")),
+    };
+    if method.is_empty() || !method.bytes().all(is_token) {
+        return Err(ParseError::BadRequest("invalid method"));
+    }
+    if !target.starts_with('/') {
+        return Err(ParseError::BadRequest(
+            "request target must be an absolute path",
+        ));
+    }
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return Err(ParseError::BadRequest("unsupported HTTP version"));
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (target, ""),
+    };
+    let path = percent_decode(path, false)?;
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(key, true)?, percent_decode(value, true)?))
+        })
+        .collect::<Result<_, ParseError>>()?;
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+    // 最后两个是空行分出来的空字符串
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ParseError::BadRequest("header line without ':'"))?;
+        // 名字和冒号之间不能有空白, 这是请求走私常用的写法
+        if name.is_empty() || !name.bytes().all(is_token) {
+            return Err(ParseError::BadRequest("invalid header name"));
+        }
+        let value = value.trim_matches(|c| c == ' ' || c == '\t');
+        headers
+            .entry(name.to_ascii_lowercase())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        path,
+        query,
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+    })
+}
+
+// 没有 Content-Length 就没有请求体; 不支持 chunked, 猜错长度会把后面的请求也读乱
+fn body_length(request: &Request, limits: Limits) -> Result<usize, ParseError> {
+    if request.header("transfer-encoding").is_some() {
+        return Err(ParseError::BadRequest("Transfer-Encoding is not supported"));
+    }
+    let value = match request.header("content-length") {
+        Some(value) => value,
+        None => return Ok(0),
+    };
+    // 重复的 Content-Length 被连成了 "5, 5", 值都一样时可以接受
+    let mut values = value.split(',').map(str::trim);
+    let first = values.next().unwrap_or_default();
+    if values.any(|other| other != first) {
+        return Err(ParseError::BadRequest("conflicting Content-Length headers"));
+    }
+    if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::BadRequest("invalid Content-Length"));
+    }
+    match first.parse::<usize>() {
+        Ok(length) if length <= limits.max_body => Ok(length),
+        // 位数太多解析不出来的也一定超过上限
+        _ => Err(ParseError::PayloadTooLarge),
+    }
+}
+
+// RFC 7230 里 token 允许的字符
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// 把 %XX 换成对应的字节, 查询参数里的 + 是空格
+fn percent_decode(s: &str, plus_as_space: bool) -> Result<String, ParseError> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(ParseError::BadRequest("invalid percent-encoding"))?;
+                decoded.push(hex);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| ParseError::BadRequest("percent-encoding is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每次 read 只给几个字节, 模拟请求被拆成好几个 TCP 包
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(self.1).min(buf.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn reader(input: &[u8], limits: Limits) -> RequestReader<Trickle<'_>> {
+        RequestReader::new(Trickle(input, 7), limits)
+    }
+
+    #[test]
+    fn parses_requests_split_across_reads() {
+        let input = b"POST /search%20it?q=rust+book&page=2&q=x HTTP/1.1\r\n\
+                      Host: localhost\r\nContent-Length: 5\r\nAccept: a\r\naccept: b\r\n\r\n\
+                      hello\
+                      GET / HTTP/1.1\r\n\r\n";
+        let mut requests = reader(input, Limits::default());
+        let first = requests.next_request().unwrap().unwrap();
+        assert_eq!("POST", first.method);
+        assert_eq!("/search it", first.path);
+        assert_eq!(Some("rust book"), first.query_param("q"));
+        assert_eq!(Some("2"), first.query_param("page"));
+        assert_eq!(3, first.query.len());
+        assert_eq!(Some("localhost"), first.header("HOST"));
+        assert_eq!(Some("a, b"), first.header("accept"));
+        assert_eq!(b"hello".to_vec(), first.body);
+
+        let second = requests.next_request().unwrap().unwrap();
+        assert_eq!(("GET", "/"), (second.method.as_str(), second.path.as_str()));
+        assert!(second.body.is_empty());
+        assert!(requests.next_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_and_oversized_requests() {
+        let status = |input: &[u8], limits| {
+            reader(input, limits)
+                .next_request()
+                .err()
+                .and_then(|e| e.status())
+                .map(|(code, _)| code)
+        };
+        let limits = Limits {
+            max_head: 64,
+            max_body: 4,
+        };
+        assert_eq!(Some(400), status(b"GET /\r\n\r\n", limits));
+        assert_eq!(
+            Some(400),
+            status(b"GET / HTTP/1.1\r\nHost : x\r\n\r\n", limits)
+        );
+        assert_eq!(Some(400), status(b"GET /%zz HTTP/1.1\r\n\r\n", limits));
+        let conflicting = b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n";
+        assert_eq!(Some(400), status(conflicting, limits));
+        assert_eq!(
+            Some(413),
+            status(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n", limits)
+        );
+        let long = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(64));
+        assert_eq!(Some(431), status(long.as_bytes(), limits));
+        // 请求没发完连接就断了, 没法回复
+        assert_eq!(None, status(b"GET / HTTP/1.1\r\nHost", limits));
+    }
+}
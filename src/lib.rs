@@ -60,6 +60,7 @@ mod back_of_house {
 
 use crate::front_of_house::hosting::add_to_waitlist;
 mod ch07;
+mod minigrep;
 
 pub fn eat_at_restaurant() {
     ch07::sound3::instrument::clarinet();
@@ -136,12 +137,33 @@ use std::io::{self, Write}; // use io 本身 和 io::Write
 // 如果希望将一个路径下 所有 公有项引入作用域，可以指定路径后跟 *
 use std::collections::*;
 
-// 个函数返回一个传递给程序的命令行参数的 迭代器（iterator）
-use std::env;
-use std::error::Error;
-use std::fs;
+use std::io::BufRead;
 use std::process;
 
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use threadPool::ThreadPool;
+
+pub use crate::minigrep::args::Command;
+pub use crate::minigrep::error::MinigrepError;
+use crate::minigrep::fuzzy;
+pub use crate::minigrep::fuzzy::{Fuzzy, FuzzyMatch};
+use crate::minigrep::index;
+pub use crate::minigrep::index::{build_index, IndexStats};
+pub use crate::minigrep::matcher::{CaseMode, MatchMode, Matcher};
+pub use crate::minigrep::output::{ColorChoice, Context, OutputMode};
+use crate::minigrep::output::{PrintOptions, Printer, Stats};
+pub use crate::minigrep::replace::Edit;
+pub use crate::minigrep::rust_item::{ItemKind, RustItem};
+pub use crate::minigrep::search::BinaryFiles;
+use crate::minigrep::search::{self, Input, SearchOptions};
+pub use crate::minigrep::searcher::{
+    Line, Match, ReaderMatches, Searcher, SearcherBuilder, StrMatches,
+};
+use crate::minigrep::tar;
+use crate::minigrep::walk::{self, FileFilter};
+
 // 二进制项目的关注分离
 // main 函数负责多个任务的组织问题在许多二进制项目中很常见。
 // 所以 Rust 社区开发出一类在 main 函数开始变得庞大时进行二进制程序的关注分离的指导性过程。
@@ -156,73 +178,389 @@ use std::process;
 //      如果 run 返回错误，则处理这个错误
 
 pub struct Config {
-    pub query: String,
-    pub filename: String,
+    // -e 和 -f 可以给出多个模式, 任意一个匹配就选中这一行
+    pub patterns: Vec<String>,
+    // 可以是文件也可以是目录, 目录会被递归搜索
+    pub paths: Vec<PathBuf>,
     pub case_sensitive: bool,
+    pub match_mode: MatchMode,
+    // 只匹配完整的单词
+    pub whole_word: bool,
+    // 在匹配行前面列出是哪些模式匹配上的
+    pub show_pattern: bool,
+    // 递归搜索时用来筛选文件的 glob
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // 递归时也进入隐藏文件, 以及不理会 .gitignore / .ignore
+    pub hidden: bool,
+    pub no_ignore: bool,
+    // 先用 minigrep index build 建好的索引排除一定不匹配的文件
+    pub index: bool,
+    // 匹配行前后额外输出的行数
+    pub context: Context,
+    pub line_number: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    pub color: ColorChoice,
+    // 同时搜索文件的线程数
+    pub threads: usize,
+    pub sort: SortBy,
+    // 输出 JSON Lines 而不是普通文本
+    pub json: bool,
+    // 选中没有匹配的行
+    pub invert: bool,
+    // 每个文件最多选中多少行
+    pub max_count: Option<usize>,
+    // 二进制文件的处理方式, 以及不是 UTF-8 的文件要不要宽松解码
+    pub binary: BinaryFiles,
+    pub lossy: bool,
+    // --replace 的模板, 以及是输出 diff 还是直接改文件
+    pub replace: Option<String>,
+    pub edit: Edit,
+    // 模糊匹配, 每个文件里的行按得分从高到低输出, 低于阈值 (0 到 100) 的不要
+    pub fuzzy: bool,
+    pub fuzzy_threshold: u8,
+    // --rust-item 按语法树找 Rust 条目, 这时没有 PATTERN, 只搜索 .rs 文件
+    pub rust_item: Option<RustItem>,
+    pub mode: OutputMode,
 }
 
-impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        // 使用 迭代器
-        args.next();
-
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
-
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
-        };
+/// 多文件输出的顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// 按搜索完成的先后输出, 多线程时顺序不固定
+    None,
+    /// 按路径排序后输出, 多线程时也是确定的顺序
+    Path,
+}
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+impl Config {
+    // 接收任意的 String 迭代器, env::args() 可以直接传进来, 测试里也能自己构造参数
+    // 只关心搜索的调用方用这个, --help / --version 也会被当成错误返回
+    pub fn new<I>(args: I) -> Result<Config, MinigrepError>
+    where
+        I: Iterator<Item = String>,
+    {
+        match Command::parse(args)? {
+            Command::Search(config) => Ok(config),
+            Command::Help => Err(MinigrepError::Usage(Command::help())),
+            Command::Version => Err(MinigrepError::Usage(Command::version())),
+            Command::DebugConfig(dump) => Err(MinigrepError::Usage(dump)),
+            Command::BuildIndex(_) => Err(MinigrepError::Usage(
+                "'minigrep index build' does not search".to_string(),
+            )),
+        }
+    }
+}
 
-        Ok(Config {
-            query,
-            filename,
-            case_sensitive,
-        })
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            patterns: Vec::new(),
+            paths: Vec::new(),
+            case_sensitive: true,
+            match_mode: MatchMode::Literal,
+            whole_word: false,
+            show_pattern: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            hidden: false,
+            no_ignore: false,
+            index: false,
+            context: Context::default(),
+            line_number: false,
+            column: false,
+            byte_offset: false,
+            color: ColorChoice::Auto,
+            threads: 1,
+            sort: SortBy::None,
+            json: false,
+            invert: false,
+            max_count: None,
+            binary: BinaryFiles::Binary,
+            lossy: false,
+            replace: None,
+            edit: Edit::default(),
+            fuzzy: false,
+            fuzzy_threshold: fuzzy::DEFAULT_THRESHOLD,
+            rust_item: None,
+            mode: OutputMode::Lines,
+        }
     }
 }
 
 // Box<dyn Error> 意味着函数会返回实现了 Error trait 的类型，
 // 不过无需指定具体将会返回的值的类型。这提供了在不同的错误场景可能有不同类型的错误返回值的灵活性。
 // 这也就是 dyn，它是 “动态的”（“dynamic”）的缩写。
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    // 直接给文件名 默认是从 根目录 开始找
-    let contents = fs::read_to_string(config.filename)?;
-
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
+// 但调用方拿到 Box<dyn Error> 之后就分不清是哪种错误了, 所以这里换成了 MinigrepError 枚举,
+// ? 会通过 From 把 io::Error / regex::Error 等转换过来
+// 返回的 bool 表示有没有找到想要的结果, main 用它决定退出码
+// (-L 时表示有没有列出文件, 其他模式表示有没有选中的行)
+pub fn run(config: Config) -> Result<bool, MinigrepError> {
+    // 查询只编译一次, 写错的正则会作为 Err 返回给 main
+    let matcher =
+        Matcher::with_patterns(&config.patterns, config.match_mode, config.case_sensitive)?
+            .whole_word(config.whole_word);
+    let patterns = Arc::new(config.patterns);
+
+    let filter = FileFilter::new(&config.include, &config.exclude)?
+        .hidden(config.hidden)
+        .ignore_files(!config.no_ignore);
+    // 没有给路径时读标准输入
+    let paths = if config.paths.is_empty() {
+        vec![PathBuf::from("-")]
     } else {
-        search_case_insensitive(&config.query, &contents)
+        config.paths
+    };
+    // 单个文件出错只报告, 接着搜索其他文件, 最后再决定退出码.
+    // 展开目录时读不了的子目录也一样
+    let mut errors = Vec::new();
+    let mut skipped = Vec::new();
+    let mut files = walk::collect_files(&paths, &filter, &mut skipped);
+    for e in skipped {
+        file_error(e, &mut errors)?;
+    }
+    if config.rust_item.is_some() {
+        files.retain(|path| {
+            path.as_os_str() == "-" || path.extension().is_some_and(|ext| ext == "rs")
+        });
+    }
+    // 搜索多个文件时需要 path:line 的前缀才分得清是哪个文件, 用索引排除文件之前就要定下来
+    // tar 包里的每个文件也要带上 archive.tar:inner/path
+    let with_filename = files.len() > 1 || files.iter().any(|path| tar::is_archive(path));
+    // -v, -L 和模糊匹配要看的正是不含查询的文件, -c 也要给它们输出 0, 索引帮不上忙
+    let prune = !config.invert
+        && !config.fuzzy
+        && !matches!(
+            config.mode,
+            OutputMode::FilesWithoutMatch | OutputMode::Count
+        );
+    if config.index && prune {
+        files = match &config.rust_item {
+            // 条目的名字一定原样出现在文件里
+            Some(item) if item.name != "*" => index::narrow(
+                &paths,
+                files,
+                std::slice::from_ref(&item.name),
+                MatchMode::Literal,
+                true,
+            )?,
+            Some(_) => files,
+            None => index::narrow(
+                &paths,
+                files,
+                &patterns,
+                config.match_mode,
+                config.case_sensitive,
+            )?,
+        };
+    }
+    if config.sort == SortBy::Path {
+        files.sort();
+    }
+
+    let mode = config.mode;
+    let search_options = SearchOptions {
+        invert: config.invert,
+        // 只关心有没有匹配时, 每个文件找到第一行就够了
+        max_count: match mode {
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch | OutputMode::Quiet => {
+                Some(1)
+            }
+            _ => config.max_count,
+        },
+        binary: config.binary,
+        // JSON 输出不是 UTF-8 的行时给出原始字节, 不会丢信息, 所以不用 --lossy 也照常搜索
+        lossy: config.lossy || config.json,
+        replace: config.replace.map(Arc::from),
+        edit: config.edit,
+        fuzzy: if config.fuzzy {
+            let query = &patterns[0];
+            let fuzzy = Fuzzy::new(query, config.case_sensitive, config.fuzzy_threshold);
+            Some(Arc::new(fuzzy))
+        } else {
+            None
+        },
+        rust_item: config.rust_item.map(Arc::new),
+    };
+    let options = PrintOptions {
+        mode,
+        // 不输出行的时候上下文也没有意义, 模糊匹配的行不按顺序输出, 也不带上下文
+        context: if mode == OutputMode::Lines && !config.fuzzy {
+            config.context
+        } else {
+            Context::default()
+        },
+        with_filename,
+        line_number: config.line_number,
+        column: config.column,
+        byte_offset: config.byte_offset,
+        show_pattern: config.show_pattern,
+        // JSON 是给程序读的, 不能混进颜色的转义序列
+        color: !config.json && config.color.should_color(),
+        json: config.json,
     };
+    let stdout = io::stdout();
+    let mut printer = Printer::new(stdout.lock(), options);
+    printer.set_patterns(Arc::clone(&patterns));
+
+    if config.threads <= 1 || files.len() <= 1 {
+        for path in files {
+            let input = Input::new(path);
+            let mut skipped = Vec::new();
+            let result = search::search_input(
+                &matcher,
+                &input,
+                &search_options,
+                &mut printer,
+                &mut skipped,
+            );
+            for e in skipped {
+                file_error(e, &mut errors)?;
+            }
+            match result {
+                // -q 找到一个就可以结束了
+                Ok(stats) if mode == OutputMode::Quiet && stats.matched_lines > 0 => break,
+                Ok(_) => {}
+                Err(e) => file_error(e, &mut errors)?,
+            }
+        }
+        let total = printer.finish()?;
+        return finish(mode, total, errors);
+    }
 
-    for line in results {
-        println!("{}", line);
+    // 每个文件在线程池里搜索, 输出先写进各自的缓冲区, 整个文件搜完再交回主线程,
+    // 这样同一个文件的结果不会和别的文件交错在一起
+    let pool = ThreadPool::new(config.threads);
+    let matcher = Arc::new(matcher);
+    let (tx, rx) = mpsc::channel();
+    let total = files.len();
+    for (index, path) in files.into_iter().enumerate() {
+        let matcher = Arc::clone(&matcher);
+        let patterns = Arc::clone(&patterns);
+        let search_options = search_options.clone();
+        let tx = tx.clone();
+        pool.execute(move || {
+            let mut file_printer = Printer::new(Vec::new(), options);
+            file_printer.set_patterns(patterns);
+            let input = Input::new(path);
+            let mut skipped = Vec::new();
+            let result = search::search_input(
+                &matcher,
+                &input,
+                &search_options,
+                &mut file_printer,
+                &mut skipped,
+            );
+            // 出错之前输出的内容也要交回去
+            let output = file_printer.into_inner();
+            // 主线程出错提前返回后接收端就没了, 这时发送失败可以忽略
+            let _ = tx.send((index, output, result, skipped));
+        });
+    }
+    drop(tx);
+
+    // --sort path 时先到的结果要等前面的文件都输出了才能输出
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (index, output, result, skipped) in rx.iter().take(total) {
+        for e in skipped {
+            file_error(e, &mut errors)?;
+        }
+        let stats = match result {
+            Ok(stats) => stats,
+            Err(e) => {
+                file_error(e, &mut errors)?;
+                Stats {
+                    searches: 1,
+                    ..Stats::default()
+                }
+            }
+        };
+        if config.sort == SortBy::None {
+            printer.file_output(&output, stats)?;
+            continue;
+        }
+        pending.insert(index, (output, stats));
+        while let Some((output, stats)) = pending.remove(&next) {
+            printer.file_output(&output, stats)?;
+            next += 1;
+        }
     }
+    let total = printer.finish()?;
 
+    finish(mode, total, errors)
+}
+
+// 打不开或者读不了的文件在标准错误上报告一下, 不影响其他文件
+// 写标准输出失败 (比如接了 head, 管道被关掉) 就没必要继续了
+fn file_error(e: MinigrepError, errors: &mut Vec<MinigrepError>) -> Result<(), MinigrepError> {
+    if e.is_output_error() {
+        return Err(e);
+    }
+    eprintln!("minigrep: {}", e);
+    errors.push(e);
     Ok(())
 }
 
+// 和 grep 一样有文件出错时要以错误退出, 除非是 -q 并且已经找到了
+fn finish(
+    mode: OutputMode,
+    total: Stats,
+    mut errors: Vec<MinigrepError>,
+) -> Result<bool, MinigrepError> {
+    let found = found(mode, total);
+    if errors.is_empty() || mode == OutputMode::Quiet && found {
+        return Ok(found);
+    }
+    Err(MinigrepError::Partial {
+        failed: errors.len(),
+        first: Box::new(errors.remove(0)),
+    })
+}
+
+fn found(mode: OutputMode, total: Stats) -> bool {
+    match mode {
+        OutputMode::FilesWithoutMatch => total.searches > total.searches_with_match,
+        _ => total.matched_lines > 0,
+    }
+}
+
 //  &String 会自动转成 &str  两个参数手动处理所有权
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    search_lines(query, contents)
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect()
+}
+
+// 额外带上行的下标 (从 0 开始), 需要输出上下文时才知道匹配行在哪
+pub fn search_lines<'a>(query: &str, contents: &'a str) -> Vec<(usize, &'a str)> {
     contents
         .lines()
-        .filter(|line| line.contains(query))
+        .enumerate()
+        .filter(|(_, line)| line.contains(query))
         .collect()
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
+// 流式版本: 从任意 BufRead (文件, 标准输入...) 里一行一行地读,
+// 每遇到一个匹配行就调用一次 on_match(行号, 内容), 不需要把所有内容读进内存
+pub fn search_reader<R, F>(query: &str, reader: R, mut on_match: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(usize, &str),
+{
+    search::for_each_line(reader, |line_number, _, line| {
+        if line.contains(query) {
+            on_match(line_number, line);
         }
-    }
-    results
+        Ok(true)
+    })
+}
+
+// 按 Unicode 简单大小写折叠逐字符比较, 不再为每一行都 to_lowercase 一份新字符串
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    Matcher::literal(query, false).search(contents)
 }
 
 #[cfg(test)]
@@ -252,6 +590,112 @@ trust me.";
             search_case_insensitive(query, contents)
         )
     }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let contents = "\
+Die Straße ist lang.
+DIE STRASSE IST LANG.
+İstanbul";
+        assert_eq!(
+            vec!["Die Straße ist lang."],
+            search_case_insensitive("STRAẞE", contents)
+        );
+        assert!(search_case_insensitive("istanbul", contents).is_empty());
+    }
+
+    #[test]
+    fn line_indices() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+        assert_eq!(
+            vec![(1, "safe, fast, productive.")],
+            search_lines("duct", contents)
+        );
+    }
+
+    #[test]
+    fn streaming() {
+        let reader = io::Cursor::new("Rust:\nsafe, fast, productive.\nPick three.\nDuct tape.");
+        let mut results = Vec::new();
+        search_reader("uct", reader, |n, line| results.push((n, line.to_string()))).unwrap();
+        assert_eq!(
+            vec![
+                (2, "safe, fast, productive.".to_string()),
+                (4, "Duct tape.".to_string())
+            ],
+            results
+        );
+    }
+
+    #[test]
+    fn context_flags() {
+        let args = ["minigrep", "-C", "2", "-A", "5", "to", "poem.txt"];
+        let config = Config::new(args.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(
+            Context {
+                before: 2,
+                after: 5
+            },
+            config.context
+        );
+    }
+
+    #[test]
+    fn position_and_color_flags() {
+        let args = [
+            "minigrep",
+            "-n",
+            "--column",
+            "--color=never",
+            "to",
+            "poem.txt",
+        ];
+        let config = Config::new(args.iter().map(|s| s.to_string())).unwrap();
+        assert!(config.line_number && config.column && !config.byte_offset);
+        assert_eq!(ColorChoice::Never, config.color);
+
+        let args = ["minigrep", "--color", "sometimes", "to", "poem.txt"];
+        assert!(Config::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn regex_flag() {
+        let args = ["minigrep", "--regex", "fa+st", "poem.txt"];
+        let config = Config::new(args.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(MatchMode::Regex, config.match_mode);
+        assert_eq!(vec!["fa+st"], config.patterns);
+        assert_eq!(vec![PathBuf::from("poem.txt")], config.paths);
+    }
+
+    #[test]
+    fn many_paths_and_globs() {
+        let args = [
+            "minigrep",
+            "--include",
+            "*.rs",
+            "fn",
+            "src",
+            "tests",
+            "--exclude",
+            "target",
+        ];
+        let config = Config::new(args.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(
+            vec![PathBuf::from("src"), PathBuf::from("tests")],
+            config.paths
+        );
+        assert_eq!(vec!["*.rs"], config.include);
+        assert_eq!(vec!["target"], config.exclude);
+        assert!(!config.hidden && !config.no_ignore);
+
+        let args = ["minigrep", "--hidden", "--no-ignore", "fn", "."];
+        let config = Config::new(args.iter().map(|s| s.to_string())).unwrap();
+        assert!(config.hidden && config.no_ignore);
+    }
 }
 /// 编写有用的文档注释
 /// Adds one to the number given.
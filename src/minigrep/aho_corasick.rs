@@ -0,0 +1,190 @@
+use crate::minigrep::matcher::{fold, Submatch};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+/// 多个字面量模式的 Aho-Corasick 自动机, 一次扫描就能找出所有模式的匹配
+///
+/// 按字符而不是按字节建立转移, 这样大小写不敏感时可以直接在折叠后的字符上跑
+pub struct AhoCorasick {
+    // goto 函数, 每个状态一个转移表, 0 是根
+    trans: Vec<HashMap<char, usize>>,
+    // 失败指针
+    fail: Vec<usize>,
+    // 到达这个状态时结束的模式: (模式下标, 模式长度(字符数)), 已经包含了失败链上的输出
+    out: Vec<Vec<(usize, usize)>>,
+    // 最长模式的字符数, 用来判断什么时候可以停止扫描
+    max_len: usize,
+    // 空模式在任何位置都匹配
+    empty: Option<usize>,
+    fold: bool,
+}
+
+impl AhoCorasick {
+    pub fn new<S: AsRef<str>>(patterns: &[S], fold_case: bool) -> AhoCorasick {
+        let mut ac = AhoCorasick {
+            trans: vec![HashMap::new()],
+            fail: vec![0],
+            out: vec![Vec::new()],
+            max_len: 0,
+            empty: None,
+            fold: fold_case,
+        };
+
+        for (index, pattern) in patterns.iter().enumerate() {
+            let mut state = 0;
+            let mut len = 0;
+            for c in pattern.as_ref().chars() {
+                let c = if fold_case { fold(c) } else { c };
+                state = match ac.trans[state].get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        let next = ac.trans.len();
+                        ac.trans.push(HashMap::new());
+                        ac.fail.push(0);
+                        ac.out.push(Vec::new());
+                        ac.trans[state].insert(c, next);
+                        next
+                    }
+                };
+                len += 1;
+            }
+            if len == 0 {
+                ac.empty.get_or_insert(index);
+            } else {
+                ac.out[state].push((index, len));
+                ac.max_len = ac.max_len.max(len);
+            }
+        }
+
+        // 按层次遍历计算失败指针, 子节点的失败指针依赖父节点的
+        let mut queue: VecDeque<usize> = ac.trans[0].values().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            let children: Vec<(char, usize)> =
+                ac.trans[state].iter().map(|(&c, &s)| (c, s)).collect();
+            for (c, child) in children {
+                let mut f = ac.fail[state];
+                let target = loop {
+                    if let Some(&next) = ac.trans[f].get(&c) {
+                        break next;
+                    }
+                    if f == 0 {
+                        break 0;
+                    }
+                    f = ac.fail[f];
+                };
+                ac.fail[child] = target;
+                let inherited = ac.out[target].clone();
+                ac.out[child].extend(inherited);
+                queue.push_back(child);
+            }
+        }
+        ac
+    }
+
+    fn next_state(&self, mut state: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.trans[state].get(&c) {
+                return next;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.fail[state];
+        }
+    }
+
+    /// 从 start 开始找最左边的匹配, 同一位置开始的取最长的
+    ///
+    /// accept 用来过滤候选 (比如 -w 的单词边界), 被拒绝的候选不会挡住后面的匹配
+    pub fn find_at<F>(&self, line: &str, start: usize, accept: F) -> Option<Submatch>
+    where
+        F: Fn(&Range<usize>) -> bool,
+    {
+        // (起始字符下标, 匹配)
+        let mut best: Option<(usize, Submatch)> = None;
+        if let Some(pattern) = self.empty {
+            if accept(&(start..start)) {
+                best = Some((
+                    0,
+                    Submatch {
+                        range: start..start,
+                        pattern,
+                    },
+                ));
+            }
+        }
+
+        let mut state = 0;
+        for (ci, (i, c)) in line[start..].char_indices().enumerate() {
+            // 比当前最优更靠左的匹配最晚也在 起点 + max_len 个字符内结束
+            if let Some((best_start, _)) = &best {
+                if ci >= best_start + self.max_len {
+                    break;
+                }
+            }
+            let folded = if self.fold { fold(c) } else { c };
+            state = self.next_state(state, folded);
+            let end = start + i + c.len_utf8();
+
+            for &(pattern, len) in &self.out[state] {
+                let start_char = ci + 1 - len;
+                let better = match &best {
+                    Some((best_start, found)) => {
+                        start_char < *best_start
+                            || start_char == *best_start && end > found.range.end
+                    }
+                    None => true,
+                };
+                if !better {
+                    continue;
+                }
+                // 从结束位置往回数 len 个字符就是起点
+                let begin = line[start..end]
+                    .char_indices()
+                    .rev()
+                    .nth(len - 1)
+                    .map_or(start, |(b, _)| start + b);
+                let range = begin..end;
+                if accept(&range) {
+                    best = Some((start_char, Submatch { range, pattern }));
+                }
+            }
+        }
+        best.map(|(_, found)| found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_all(ac: &AhoCorasick, line: &str) -> Vec<(Range<usize>, usize)> {
+        let mut all = Vec::new();
+        let mut start = 0;
+        while let Some(found) = ac.find_at(line, start, |_| true) {
+            start = found.range.end;
+            all.push((found.range, found.pattern));
+        }
+        all
+    }
+
+    #[test]
+    fn leftmost_longest() {
+        let ac = AhoCorasick::new(&["he", "she", "hers", "his"], false);
+        assert_eq!(vec![(1..4, 1), (4..7, 3)], find_all(&ac, "ushehis"));
+        assert_eq!(vec![(0..4, 2)], find_all(&ac, "hers"));
+    }
+
+    #[test]
+    fn folded_and_multibyte() {
+        let ac = AhoCorasick::new(&["straße", "ΟΔΟΣ"], true);
+        assert_eq!(vec![(1..9, 0), (10..18, 1)], find_all(&ac, "(STRAẞE οδος)"));
+    }
+
+    #[test]
+    fn rejected_candidates_do_not_hide_later_matches() {
+        let ac = AhoCorasick::new(&["ab", "b"], false);
+        let found = ac.find_at("ab", 0, |r| r.start > 0).unwrap();
+        assert_eq!(1..2, found.range);
+    }
+}
@@ -0,0 +1,1096 @@
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::rc::{self, Source};
+use crate::{
+    BinaryFiles, CaseMode, ColorChoice, Config, Edit, MatchMode, OutputMode, RustItem, SortBy,
+};
+use std::fs;
+use std::path::PathBuf;
+
+/// 解析命令行之后要做的事
+pub enum Command {
+    Search(Config),
+    Help,
+    Version,
+    /// --debug-config: 每个设置是从哪个配置文件, 环境变量或者命令行来的
+    DebugConfig(String),
+    /// minigrep index build DIR: 建立或更新 DIR 下的索引
+    BuildIndex(PathBuf),
+}
+
+// 一个命令行选项的描述, 解析和 --help 的输出都从这张表来
+struct Opt {
+    short: Option<char>,
+    long: &'static str,
+    // 需要参数时给出参数在帮助里的名字
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+const OPTIONS: &[Opt] = &[
+    Opt {
+        short: Some('e'),
+        long: "regexp",
+        value: Some("PATTERN"),
+        help: "use PATTERN as the query, may be given more than once",
+    },
+    Opt {
+        short: Some('f'),
+        long: "file",
+        value: Some("FILE"),
+        help: "read patterns from FILE, one per line",
+    },
+    Opt {
+        short: Some('i'),
+        long: "ignore-case",
+        value: None,
+        help: "case insensitive search",
+    },
+    Opt {
+        short: Some('s'),
+        long: "case-sensitive",
+        value: None,
+        help: "case sensitive search (overrides CASE_INSENSITIVE)",
+    },
+    Opt {
+        short: Some('S'),
+        long: "smart-case",
+        value: None,
+        help: "ignore case unless the pattern has an uppercase letter",
+    },
+    Opt {
+        short: Some('w'),
+        long: "word-regexp",
+        value: None,
+        help: "only match whole words",
+    },
+    Opt {
+        short: None,
+        long: "show-pattern",
+        value: None,
+        help: "print which patterns matched before each line",
+    },
+    Opt {
+        short: None,
+        long: "regex",
+        value: None,
+        help: "treat the pattern as a regular expression",
+    },
+    Opt {
+        short: None,
+        long: "fuzzy",
+        value: None,
+        help: "rank lines by how closely they fuzzy match the pattern",
+    },
+    Opt {
+        short: None,
+        long: "fuzzy-threshold",
+        value: Some("NUM"),
+        help: "with --fuzzy, drop lines scoring below NUM (0-100, default 50)",
+    },
+    Opt {
+        short: None,
+        long: "rust-item",
+        value: Some("KIND:NAME"),
+        help: "find Rust items such as fn:search or struct:Config instead of lines",
+    },
+    Opt {
+        short: Some('r'),
+        long: "replace",
+        value: Some("REPL"),
+        help: "print matches replaced with REPL ($1, ${name} for groups)",
+    },
+    Opt {
+        short: None,
+        long: "diff",
+        value: None,
+        help: "with --replace, show the changes as a unified diff",
+    },
+    Opt {
+        short: None,
+        long: "in-place",
+        value: None,
+        help: "with --replace, rewrite the files",
+    },
+    Opt {
+        short: Some('a'),
+        long: "text",
+        value: None,
+        help: "search binary files as if they were text",
+    },
+    Opt {
+        short: Some('I'),
+        long: "ignore-binary",
+        value: None,
+        help: "treat binary files as if they did not match",
+    },
+    Opt {
+        short: None,
+        long: "lossy",
+        value: None,
+        help: "replace invalid UTF-8 instead of failing the file",
+    },
+    Opt {
+        short: Some('v'),
+        long: "invert-match",
+        value: None,
+        help: "select lines that do not match",
+    },
+    Opt {
+        short: Some('m'),
+        long: "max-count",
+        value: Some("NUM"),
+        help: "stop reading a file after NUM selected lines",
+    },
+    Opt {
+        short: Some('c'),
+        long: "count",
+        value: None,
+        help: "only print the number of selected lines per file",
+    },
+    Opt {
+        short: Some('l'),
+        long: "files-with-matches",
+        value: None,
+        help: "only print names of files with selected lines",
+    },
+    Opt {
+        short: Some('L'),
+        long: "files-without-match",
+        value: None,
+        help: "only print names of files without selected lines",
+    },
+    Opt {
+        short: Some('q'),
+        long: "quiet",
+        value: None,
+        help: "print nothing, exit 0 on the first selected line",
+    },
+    Opt {
+        short: None,
+        long: "include",
+        value: Some("GLOB"),
+        help: "only search files matching GLOB (repeatable)",
+    },
+    Opt {
+        short: None,
+        long: "exclude",
+        value: Some("GLOB"),
+        help: "skip files and directories matching GLOB (repeatable)",
+    },
+    Opt {
+        short: None,
+        long: "index",
+        value: None,
+        help: "skip files that the index from 'minigrep index build' rules out",
+    },
+    Opt {
+        short: None,
+        long: "hidden",
+        value: None,
+        help: "search hidden files and directories",
+    },
+    Opt {
+        short: None,
+        long: "no-ignore",
+        value: None,
+        help: "don't respect .gitignore and .ignore files",
+    },
+    Opt {
+        short: Some('A'),
+        long: "after-context",
+        value: Some("NUM"),
+        help: "print NUM lines after each match",
+    },
+    Opt {
+        short: Some('B'),
+        long: "before-context",
+        value: Some("NUM"),
+        help: "print NUM lines before each match",
+    },
+    Opt {
+        short: Some('C'),
+        long: "context",
+        value: Some("NUM"),
+        help: "print NUM lines before and after each match",
+    },
+    Opt {
+        short: Some('n'),
+        long: "line-number",
+        value: None,
+        help: "prefix each line with its line number",
+    },
+    Opt {
+        short: None,
+        long: "column",
+        value: None,
+        help: "prefix each match with the column of the first match",
+    },
+    Opt {
+        short: Some('b'),
+        long: "byte-offset",
+        value: None,
+        help: "prefix each line with its byte offset in the file",
+    },
+    Opt {
+        short: None,
+        long: "color",
+        value: Some("WHEN"),
+        help: "highlight matches: always, never or auto",
+    },
+    Opt {
+        short: None,
+        long: "json",
+        value: None,
+        help: "print results as JSON Lines (begin, match, context, end, summary)",
+    },
+    Opt {
+        short: Some('j'),
+        long: "threads",
+        value: Some("NUM"),
+        help: "search files with NUM worker threads",
+    },
+    Opt {
+        short: None,
+        long: "sort",
+        value: Some("KEY"),
+        help: "print files in a stable order, KEY must be 'path'",
+    },
+    Opt {
+        short: None,
+        long: "no-config",
+        value: None,
+        help: "don't read .minigreprc files",
+    },
+    Opt {
+        short: None,
+        long: "debug-config",
+        value: None,
+        help: "show where each setting comes from and exit",
+    },
+    Opt {
+        short: Some('h'),
+        long: "help",
+        value: None,
+        help: "print this help and exit",
+    },
+    Opt {
+        short: Some('V'),
+        long: "version",
+        value: None,
+        help: "print version information and exit",
+    },
+];
+
+impl Command {
+    /// 解析命令行参数, 第一个参数是程序名会被跳过
+    ///
+    /// 长选项支持 `--name value` 和 `--name=value`, 短选项可以合并 (`-in`),
+    /// 需要参数的短选项可以直接跟参数 (`-A3`), `--` 之后的参数都当作位置参数
+    ///
+    /// 命令行前面依次垫上配置文件和环境变量里的参数, 后出现的覆盖先出现的
+    pub fn parse<I>(args: I) -> Result<Command, MinigrepError>
+    where
+        I: Iterator<Item = String>,
+    {
+        let args: Vec<String> = args.skip(1).collect();
+        // 要搜索 "index" 这个词时用 -e index
+        if let [index, build, rest @ ..] = &args[..] {
+            if index == "index" && build == "build" {
+                return match rest {
+                    [] => Ok(Command::BuildIndex(PathBuf::from("."))),
+                    [dir] => Ok(Command::BuildIndex(PathBuf::from(dir))),
+                    _ => Err(MinigrepError::Usage(
+                        "usage: minigrep index build [DIR]".to_string(),
+                    )),
+                };
+            }
+        }
+        // 要在读配置文件之前知道
+        let no_config = has_no_config(&args);
+        let mut layered = Vec::new();
+        let mut rc_files = Vec::new();
+        if !no_config {
+            for path in rc::rc_files() {
+                let found = rc::read_rc(&path)?;
+                rc_files.push((path, found.is_some()));
+                layered.extend(found.unwrap_or_default());
+            }
+        }
+        layered.extend(rc::env_args());
+        layered.extend(args.into_iter().map(|arg| (arg, Source::CommandLine)));
+        parse(layered, (!no_config).then_some(&rc_files[..]))
+    }
+
+    /// --help 的内容
+    pub fn help() -> String {
+        let mut help = String::from(
+            "Usage: minigrep [OPTIONS] PATTERN [PATH...]\n       \
+             minigrep [OPTIONS] -e PATTERN... [-f FILE...] [PATH...]\n       \
+             minigrep [OPTIONS] --rust-item KIND:NAME [PATH...]\n       \
+             minigrep index build [DIR]\n\n\
+             Search for PATTERN in each file, directories are searched recursively\n\
+             skipping hidden files and paths matched by .gitignore or .ignore files.\n\
+             Files ending in .tar are searched entry by entry (archive.tar:inner/path).\n\
+             A line is selected if any of the patterns matches it.\n\
+             With no PATH, or when PATH is -, read standard input.\n\
+             --rust-item parses .rs files and prints path:first-last:item for each\n\
+             fn, struct, enum, union, trait, mod, macro, const, static or type named\n\
+             NAME (* for any), ignoring comments and strings.\n\n\
+             Options:\n",
+        );
+        for opt in OPTIONS {
+            let short = match opt.short {
+                Some(c) => format!("-{}, ", c),
+                None => "    ".to_string(),
+            };
+            let long = match opt.value {
+                Some(value) => format!("--{} {}", opt.long, value),
+                None => format!("--{}", opt.long),
+            };
+            help.push_str(&format!("  {}{:<24}{}\n", short, long, opt.help));
+        }
+        help.push_str(
+            "\nDefault options are read from ~/.minigreprc and the nearest .minigreprc\n\
+             above the current directory (one option per line), then from\n\
+             CASE_INSENSITIVE (same as -i) and MINIGREP_OPTS, then from the command line.\n\
+             Flags without a value can be turned off again with --no-FLAG (--no-json,\n\
+             --no-line-number, ...); --ignore undoes --no-ignore.\n\
+             Exit status is 0 if a line is selected, 1 if none is, 2 for usage errors,\n\
+             3 for I/O errors, 4 for invalid patterns and 5 for invalid UTF-8.\n",
+        );
+        help
+    }
+
+    /// --version 的内容
+    pub fn version() -> String {
+        format!("minigrep {}", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+// 只能写在命令行上的选项: 配置文件里的模式会让命令行上的模式被当成路径
+const COMMAND_LINE_ONLY: &[&str] = &[
+    "regexp",
+    "file",
+    "no-config",
+    "debug-config",
+    "help",
+    "version",
+];
+
+// 命令行上有没有 --no-config. 和 parse 一样跳过选项的参数,
+// `-e --no-config` 是在搜索 "--no-config", 不能因此不读配置文件
+fn has_no_config(args: &[String]) -> bool {
+    let takes_value = |opt: &Opt| opt.value.is_some();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            if long == "no-config" {
+                return true;
+            }
+            if OPTIONS.iter().any(|o| o.long == long && takes_value(o)) {
+                args.next();
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // 第一个要参数的短选项把剩下的部分当作参数, 没有剩下的才取下一个
+            let shorts = &arg[1..];
+            for (i, c) in shorts.char_indices() {
+                if OPTIONS.iter().any(|o| o.short == Some(c) && takes_value(o)) {
+                    if i + c.len_utf8() == shorts.len() {
+                        args.next();
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    false
+}
+
+// rc_files 是找过的配置文件以及是否存在, --no-config 时是 None
+fn parse(
+    args: Vec<(String, Source)>,
+    rc_files: Option<&[(PathBuf, bool)]>,
+) -> Result<Command, MinigrepError> {
+    let mut args = args.into_iter();
+    let mut config = Config::default();
+    // 通过 -e / -f 给出的模式, 都没有时第一个位置参数才是模式
+    let mut patterns = None;
+    let mut case = None;
+    let mut settings = Settings::default();
+    let mut positional = Vec::new();
+
+    while let Some((arg, source)) = args.next() {
+        if !source.is_command_line() && (arg == "--" || !arg.starts_with('-') || arg == "-") {
+            return Err(MinigrepError::Usage(format!(
+                "{}: '{}' is not an option, only options can be set there",
+                source, arg
+            )));
+        }
+        if arg == "--" {
+            positional.extend(args.map(|(arg, _)| arg));
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            if let Some(opt) = negated(name) {
+                if inline.is_some() {
+                    return Err(MinigrepError::Usage(format!(
+                        "option '--{}' does not take a value",
+                        name
+                    )));
+                }
+                settings.record(opt, Some(name), None, &source)?;
+                negate(opt, &mut config, &mut case);
+                continue;
+            }
+            let opt = OPTIONS
+                .iter()
+                .find(|o| o.long == name)
+                .ok_or_else(|| format!("unknown option '--{}'", name))?;
+            let value = match (opt.value, inline) {
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(next_value(&mut args, &source, &arg)?),
+                (None, Some(_)) => {
+                    return Err(MinigrepError::Usage(format!(
+                        "option '--{}' does not take a value",
+                        name
+                    )))
+                }
+                (None, None) => None,
+            };
+            settings.record(opt, None, value.as_deref(), &source)?;
+            if let Some(command) = apply(opt, value, &mut config, &mut patterns, &mut case)? {
+                return Ok(command);
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            let shorts = &arg[1..];
+            for (i, c) in shorts.char_indices() {
+                let opt = OPTIONS
+                    .iter()
+                    .find(|o| o.short == Some(c))
+                    .ok_or_else(|| format!("unknown option '-{}'", c))?;
+                let value = if opt.value.is_some() {
+                    // 剩下的部分就是参数, 没有的话取下一个命令行参数
+                    let rest = &shorts[i + c.len_utf8()..];
+                    if rest.is_empty() {
+                        Some(next_value(&mut args, &source, &format!("-{}", c))?)
+                    } else {
+                        Some(rest.to_string())
+                    }
+                } else {
+                    None
+                };
+                let takes_value = value.is_some();
+                settings.record(opt, None, value.as_deref(), &source)?;
+                if let Some(command) = apply(opt, value, &mut config, &mut patterns, &mut case)? {
+                    return Ok(command);
+                }
+                if takes_value {
+                    break;
+                }
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    if settings.has("debug-config") {
+        return Ok(Command::DebugConfig(settings.dump(rc_files)));
+    }
+
+    let mut positional = positional.into_iter();
+    config.patterns = match patterns {
+        Some(_) if config.rust_item.is_some() => {
+            return Err(MinigrepError::Usage(
+                "'--rust-item' cannot be used with '--regexp' or '--file'".to_string(),
+            ))
+        }
+        Some(patterns) => patterns,
+        // --rust-item 本身就是查询, 剩下的都是路径
+        None if config.rust_item.is_some() => Vec::new(),
+        None => vec![positional
+            .next()
+            .ok_or_else(|| "missing PATTERN (see --help)".to_string())?],
+    };
+    // 没有给文件时读标准输入
+    config.paths = positional.map(PathBuf::from).collect();
+
+    // CASE_INSENSITIVE 已经作为 --ignore-case 排在命令行前面了
+    config.case_sensitive = case.unwrap_or_default().is_sensitive(&config.patterns);
+
+    check_replace(&config)?;
+    check_fuzzy(&config)?;
+    check_rust_item(&config)?;
+    check_json(&config)?;
+    Ok(Command::Search(config))
+}
+
+// --replace 相关的选项组合是否说得通
+fn check_replace(config: &Config) -> Result<(), MinigrepError> {
+    let editing = config.edit != Edit::default();
+    let problem = if editing && config.replace.is_none() {
+        "'--diff' and '--in-place' need '--replace'"
+    } else if config.replace.is_some() && config.invert {
+        "'--replace' cannot be used with '--invert-match'"
+    } else if editing && config.json {
+        "'--diff' and '--in-place' cannot be used with '--json'"
+    } else {
+        return Ok(());
+    };
+    Err(MinigrepError::Usage(problem.to_string()))
+}
+
+// 模糊匹配只认一个普通的查询, 而且输出的是按得分排好的行
+fn check_fuzzy(config: &Config) -> Result<(), MinigrepError> {
+    if !config.fuzzy {
+        return Ok(());
+    }
+    let problem = if config.patterns.len() != 1 {
+        "'--fuzzy' takes exactly one pattern"
+    } else if config.match_mode == MatchMode::Regex || config.whole_word {
+        "'--fuzzy' cannot be used with '--regex' or '--word-regexp'"
+    } else if config.replace.is_some() || config.invert {
+        "'--fuzzy' cannot be used with '--replace' or '--invert-match'"
+    } else {
+        return Ok(());
+    };
+    Err(MinigrepError::Usage(problem.to_string()))
+}
+
+// --rust-item 输出的是条目而不是行, 和改变选中或输出行的选项都不搭
+fn check_rust_item(config: &Config) -> Result<(), MinigrepError> {
+    if config.rust_item.is_none() {
+        return Ok(());
+    }
+    let problem = if config.match_mode == MatchMode::Regex || config.fuzzy {
+        "'--rust-item' cannot be used with '--regex' or '--fuzzy'"
+    } else if config.replace.is_some() || config.invert {
+        "'--rust-item' cannot be used with '--replace' or '--invert-match'"
+    } else {
+        return Ok(());
+    };
+    Err(MinigrepError::Usage(problem.to_string()))
+}
+
+// -c / -l / -L 输出的是数字和文件名, 混在 JSON Lines 里会让逐行解析的程序出错;
+// 要这些信息可以看 --json 的 end 和 summary 事件里的统计
+fn check_json(config: &Config) -> Result<(), MinigrepError> {
+    let listing = matches!(
+        config.mode,
+        OutputMode::Count | OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch
+    );
+    if config.json && listing {
+        return Err(MinigrepError::Usage(
+            "'--json' cannot be used with '--count', '--files-with-matches' or '--files-without-match'"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// 选项的参数是下一个参数时, 它必须和选项来自同一个地方:
+// 配置文件最后一行的 --replace 不能把命令行上的 PATTERN 当成自己的参数
+fn next_value<I>(args: &mut I, source: &Source, option: &str) -> Result<String, String>
+where
+    I: Iterator<Item = (String, Source)>,
+{
+    match args.next() {
+        Some((value, from)) if from == *source => Ok(value),
+        _ if source.is_command_line() => Err(format!("option '{}' requires a value", option)),
+        _ => Err(format!("{}: option '{}' requires a value", source, option)),
+    }
+}
+
+// 可以用 --no-NAME 关掉的开关, 用来撤销配置文件或 MINIGREP_OPTS 里打开的选项
+const NEGATABLE: &[&str] = &[
+    "ignore-case",
+    "smart-case",
+    "word-regexp",
+    "show-pattern",
+    "regex",
+    "fuzzy",
+    "diff",
+    "in-place",
+    "text",
+    "ignore-binary",
+    "lossy",
+    "invert-match",
+    "count",
+    "files-with-matches",
+    "files-without-match",
+    "quiet",
+    "index",
+    "hidden",
+    "line-number",
+    "column",
+    "byte-offset",
+    "json",
+];
+
+// name 是不是某个开关的否定形式: --no-json 之类, 以及撤销 --no-ignore 的 --ignore
+fn negated(name: &str) -> Option<&'static Opt> {
+    let long = match name {
+        "ignore" => "no-ignore",
+        _ => name
+            .strip_prefix("no-")
+            .filter(|long| NEGATABLE.contains(long))?,
+    };
+    OPTIONS.iter().find(|o| o.long == long)
+}
+
+// 把开关恢复成默认值, 互相覆盖的一组选项 (比如 -c / -l / -q) 关掉任意一个都回到默认
+fn negate(opt: &Opt, config: &mut Config, case: &mut Option<CaseMode>) {
+    match opt.long {
+        "ignore-case" | "smart-case" => *case = None,
+        "word-regexp" => config.whole_word = false,
+        "show-pattern" => config.show_pattern = false,
+        "regex" => config.match_mode = MatchMode::Literal,
+        "fuzzy" => config.fuzzy = false,
+        "diff" => config.edit.diff = false,
+        "in-place" => config.edit.in_place = false,
+        "text" | "ignore-binary" => config.binary = BinaryFiles::Binary,
+        "lossy" => config.lossy = false,
+        "invert-match" => config.invert = false,
+        "count" | "files-with-matches" | "files-without-match" | "quiet" => {
+            config.mode = OutputMode::Lines
+        }
+        "index" => config.index = false,
+        "hidden" => config.hidden = false,
+        "no-ignore" => config.no_ignore = false,
+        "line-number" => config.line_number = false,
+        "column" => config.column = false,
+        "byte-offset" => config.byte_offset = false,
+        "json" => config.json = false,
+        _ => unreachable!("option '--{}' cannot be negated", opt.long),
+    }
+}
+
+// 把一个选项应用到 config 上, --help / --version 直接返回对应的命令
+fn apply(
+    opt: &Opt,
+    value: Option<String>,
+    config: &mut Config,
+    patterns: &mut Option<Vec<String>>,
+    case: &mut Option<CaseMode>,
+) -> Result<Option<Command>, MinigrepError> {
+    let value = value.unwrap_or_default();
+    match opt.long {
+        "regexp" => patterns.get_or_insert_with(Vec::new).push(value),
+        "file" => {
+            let contents = fs::read_to_string(&value).map_err(|e| MinigrepError::io(&value, e))?;
+            // 和 grep 一样, 空文件表示没有模式, 什么都不匹配
+            patterns
+                .get_or_insert_with(Vec::new)
+                .extend(contents.lines().map(String::from));
+        }
+        "ignore-case" => *case = Some(CaseMode::Insensitive),
+        "case-sensitive" => *case = Some(CaseMode::Sensitive),
+        "smart-case" => *case = Some(CaseMode::Smart),
+        "word-regexp" => config.whole_word = true,
+        "show-pattern" => config.show_pattern = true,
+        "regex" => config.match_mode = MatchMode::Regex,
+        "fuzzy" => config.fuzzy = true,
+        "fuzzy-threshold" => {
+            config.fuzzy_threshold = match number(opt, &value)? {
+                n @ 0..=100 => n as u8,
+                _ => {
+                    return Err(MinigrepError::Usage(
+                        "'--fuzzy-threshold' must be between 0 and 100".to_string(),
+                    ))
+                }
+            }
+        }
+        "rust-item" => config.rust_item = Some(RustItem::parse(&value)?),
+        "text" => config.binary = BinaryFiles::Text,
+        "ignore-binary" => config.binary = BinaryFiles::WithoutMatch,
+        "lossy" => config.lossy = true,
+        "replace" => config.replace = Some(value),
+        "diff" => config.edit.diff = true,
+        "in-place" => config.edit.in_place = true,
+        "invert-match" => config.invert = true,
+        "max-count" => config.max_count = Some(number(opt, &value)?),
+        "count" => config.mode = OutputMode::Count,
+        "files-with-matches" => config.mode = OutputMode::FilesWithMatches,
+        "files-without-match" => config.mode = OutputMode::FilesWithoutMatch,
+        "quiet" => config.mode = OutputMode::Quiet,
+        "include" => config.include.push(value),
+        "exclude" => config.exclude.push(value),
+        "hidden" => config.hidden = true,
+        "no-ignore" => config.no_ignore = true,
+        "index" => config.index = true,
+        "after-context" => config.context.after = number(opt, &value)?,
+        "before-context" => config.context.before = number(opt, &value)?,
+        "context" => {
+            let lines = number(opt, &value)?;
+            config.context.before = lines;
+            config.context.after = lines;
+        }
+        "line-number" => config.line_number = true,
+        "column" => config.column = true,
+        "byte-offset" => config.byte_offset = true,
+        "color" => {
+            config.color = ColorChoice::parse(&value).ok_or_else(|| {
+                format!(
+                    "invalid value '{}' for '--color' (expected always, never or auto)",
+                    value
+                )
+            })?
+        }
+        "json" => config.json = true,
+        "threads" => {
+            config.threads = number(opt, &value)?;
+            if config.threads == 0 {
+                return Err(MinigrepError::Usage(
+                    "'--threads' must be at least 1".to_string(),
+                ));
+            }
+        }
+        "sort" => {
+            config.sort = match value.as_str() {
+                "path" => SortBy::Path,
+                _ => {
+                    return Err(MinigrepError::Usage(format!(
+                        "invalid value '{}' for '--sort' (expected path)",
+                        value
+                    )))
+                }
+            }
+        }
+        // 在读配置文件之前和解析完之后处理
+        "no-config" | "debug-config" => {}
+        "help" => return Ok(Some(Command::Help)),
+        "version" => return Ok(Some(Command::Version)),
+        _ => unreachable!("option '--{}' is not handled", opt.long),
+    }
+    Ok(None)
+}
+
+// --debug-config 用: 每个设置最后一次是在哪里给出的, 按给出的顺序
+#[derive(Default)]
+struct Settings(Vec<(&'static str, String, Source)>);
+
+// 可以给多次并且每次都有效的选项, 其他的后出现的覆盖先出现的
+const REPEATABLE: &[&str] = &["regexp", "file", "include", "exclude"];
+
+impl Settings {
+    // negated 是 --no-json 这样撤销 opt 的写法, 这时记下的就是它
+    fn record(
+        &mut self,
+        opt: &Opt,
+        negated: Option<&str>,
+        value: Option<&str>,
+        source: &Source,
+    ) -> Result<(), String> {
+        if !source.is_command_line() && COMMAND_LINE_ONLY.contains(&opt.long) {
+            return Err(format!(
+                "{}: '--{}' can only be given on the command line",
+                source, opt.long
+            ));
+        }
+        // 互相覆盖的几个选项算同一个设置
+        let key = match opt.long {
+            "ignore-case" | "case-sensitive" | "smart-case" => "case",
+            "count" | "files-with-matches" | "files-without-match" | "quiet" => "output",
+            "text" | "ignore-binary" => "binary-files",
+            long => long,
+        };
+        if !REPEATABLE.contains(&key) {
+            self.0.retain(|(k, _, _)| *k != key);
+        }
+        let arg = match (negated, value) {
+            (Some(negated), _) => format!("--{}", negated),
+            (None, Some(value)) => format!("--{}={}", opt.long, value),
+            (None, None) => format!("--{}", opt.long),
+        };
+        self.0.push((key, arg, source.clone()));
+        Ok(())
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.0.iter().any(|(k, _, _)| *k == key)
+    }
+
+    fn dump(&self, rc_files: Option<&[(PathBuf, bool)]>) -> String {
+        let mut out = String::from("config files:\n");
+        match rc_files {
+            None => out.push_str("  (disabled by --no-config)\n"),
+            Some([]) => out.push_str("  (none)\n"),
+            Some(files) => {
+                for (path, found) in files {
+                    let note = if *found { "" } else { " (not found)" };
+                    out.push_str(&format!("  {}{}\n", path.display(), note));
+                }
+            }
+        }
+        out.push_str("settings:\n");
+        for (_, arg, source) in &self.0 {
+            out.push_str(&format!("  {:<30} {}\n", arg, source));
+        }
+        out
+    }
+}
+
+fn number(opt: &Opt, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number '{}' for '--{}'", value, opt.long))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ItemKind;
+    use std::env;
+    use std::path::Path;
+
+    // 不读配置文件和环境变量, 测试结果不受运行环境影响
+    fn parse_args(args: &[&str]) -> Result<Command, MinigrepError> {
+        let args = args
+            .iter()
+            .map(|arg| (arg.to_string(), Source::CommandLine))
+            .collect();
+        parse(args, None)
+    }
+
+    fn config(args: &[&str]) -> Config {
+        match parse_args(args) {
+            Ok(Command::Search(config)) => config,
+            Ok(_) => panic!("expected a search"),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let config = config(&["-winA2", "body", "poem.txt"]);
+        assert!(!config.case_sensitive);
+        assert!(config.whole_word);
+        assert!(config.line_number);
+        assert_eq!(2, config.context.after);
+    }
+
+    #[test]
+    fn pattern_after_double_dash() {
+        let config = config(&["-e", "-v", "--", "--poem.txt"]);
+        assert_eq!(vec!["-v"], config.patterns);
+        assert_eq!(vec![PathBuf::from("--poem.txt")], config.paths);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(!config(&["--smart-case", "body", "poem.txt"]).case_sensitive);
+        assert!(config(&["-S", "Body", "poem.txt"]).case_sensitive);
+        assert!(config(&["-S", "-e", "body", "-e", "Frog", "poem.txt"]).case_sensitive);
+    }
+
+    #[test]
+    fn many_patterns() {
+        let path = env::temp_dir().join(format!("minigrep-patterns-{}", std::process::id()));
+        fs::write(&path, "unwrap\nexpect(\n").unwrap();
+        let file = path.to_str().unwrap();
+
+        let config = config(&["-e", "panic!", "-f", file, "--show-pattern", "src"]);
+        assert_eq!(vec!["panic!", "unwrap", "expect("], config.patterns);
+        assert_eq!(vec![PathBuf::from("src")], config.paths);
+        assert!(config.show_pattern);
+
+        fs::remove_file(&path).unwrap();
+        let err = parse_args(&["-f", file, "src"]).err().unwrap();
+        assert!(matches!(err, MinigrepError::Io { path: Some(p), .. } if p == path));
+    }
+
+    #[test]
+    fn threads_and_sort() {
+        let config = config(&["-j4", "--sort", "path", "fn", "src"]);
+        assert_eq!(4, config.threads);
+        assert_eq!(SortBy::Path, config.sort);
+        assert!(parse_args(&["-j", "0", "fn", "src"]).is_err());
+    }
+
+    #[test]
+    fn selection_modes() {
+        let config = config(&["-vm", "3", "-c", "fn", "src"]);
+        assert!(config.invert);
+        assert_eq!(Some(3), config.max_count);
+        assert_eq!(OutputMode::Count, config.mode);
+        assert_eq!(OutputMode::Quiet, self::config(&["-lq", "fn"]).mode);
+    }
+
+    #[test]
+    fn binary_and_encoding() {
+        assert_eq!(BinaryFiles::Binary, config(&["fn", "src"]).binary);
+        assert_eq!(BinaryFiles::Text, config(&["-a", "fn", "src"]).binary);
+        let config = config(&["-I", "--lossy", "fn", "src"]);
+        assert_eq!(BinaryFiles::WithoutMatch, config.binary);
+        assert!(config.lossy);
+    }
+
+    #[test]
+    fn replace_options() {
+        let config = config(&[
+            "--regex",
+            "-r",
+            "$1",
+            "--diff",
+            "--in-place",
+            "f(o+)",
+            "src",
+        ]);
+        assert_eq!(Some("$1".to_string()), config.replace);
+        assert!(config.edit.diff && config.edit.in_place);
+        assert!(parse_args(&["--diff", "foo", "src"]).is_err());
+        assert!(parse_args(&["-v", "-r", "bar", "foo", "src"]).is_err());
+    }
+
+    #[test]
+    fn json_rejects_count_and_file_lists() {
+        assert!(config(&["--json", "-q", "a", "f.txt"]).json);
+        for flag in ["-c", "-l", "-L"] {
+            match parse_args(&["--json", flag, "a", "f.txt"]) {
+                Err(MinigrepError::Usage(_)) => {}
+                _ => panic!("'--json {}' should be a usage error", flag),
+            }
+        }
+    }
+
+    #[test]
+    fn fuzzy_options() {
+        let config = config(&["--fuzzy", "--fuzzy-threshold=70", "drery", "poem.txt"]);
+        assert!(config.fuzzy);
+        assert_eq!(70, config.fuzzy_threshold);
+        assert!(parse_args(&["--fuzzy-threshold", "101", "--fuzzy", "a"]).is_err());
+        assert!(parse_args(&["--fuzzy", "-e", "a", "-e", "b"]).is_err());
+        assert!(parse_args(&["--fuzzy", "--regex", "a.b"]).is_err());
+    }
+
+    #[test]
+    fn rust_item_options() {
+        let config = config(&["--rust-item", "struct:Config", "src", "lib.rs"]);
+        let item = config.rust_item.unwrap();
+        assert_eq!(
+            (ItemKind::Struct, "Config"),
+            (item.kind, item.name.as_str())
+        );
+        assert!(config.patterns.is_empty());
+        assert_eq!(
+            vec![PathBuf::from("src"), PathBuf::from("lib.rs")],
+            config.paths
+        );
+        assert!(parse_args(&["--rust-item", "function:main"]).is_err());
+        assert!(parse_args(&["--rust-item", "fn:main", "-e", "main"]).is_err());
+        assert!(parse_args(&["--rust-item", "fn:main", "-v", "src"]).is_err());
+    }
+
+    #[test]
+    fn no_config_is_not_an_option_value() {
+        let no_config = |args: &[&str]| {
+            has_no_config(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+        };
+        assert!(no_config(&["-n", "--no-config", "here", "g.txt"]));
+        assert!(no_config(&["--regexp=x", "--no-config", "g.txt"]));
+        assert!(!no_config(&["-e", "--no-config", "g.txt"]));
+        assert!(!no_config(&["-ie", "--no-config", "g.txt"]));
+        assert!(!no_config(&["--replace", "--no-config", "x", "g.txt"]));
+        assert!(!no_config(&["--", "--no-config"]));
+        // -e 后面直接跟着模式时, 下一个参数就是选项了
+        assert!(no_config(&["-ehere", "--no-config", "g.txt"]));
+    }
+
+    #[test]
+    fn layered_config() {
+        let rc = Path::new("/home/me/.minigreprc");
+        let mut args = rc::parse_rc(rc, "--smart-case\n-n\n--max-count=5\n");
+        args.push(("-m9".to_string(), Source::Env(rc::OPTS_ENV)));
+        let cli = ["-i", "body", "poem.txt"];
+        args.extend(cli.iter().map(|arg| (arg.to_string(), Source::CommandLine)));
+
+        let config = match parse(args.clone(), None).unwrap() {
+            Command::Search(config) => config,
+            _ => panic!("expected a search"),
+        };
+        // 命令行的 -i 覆盖了配置文件里的 --smart-case, 环境变量覆盖了配置文件的 -m
+        assert!(!config.case_sensitive);
+        assert!(config.line_number);
+        assert_eq!(Some(9), config.max_count);
+
+        args.push(("--debug-config".to_string(), Source::CommandLine));
+        let dump = match parse(args, Some(&[(rc.to_path_buf(), true)])) {
+            Ok(Command::DebugConfig(dump)) => dump,
+            _ => panic!("expected a config dump"),
+        };
+        let line = |arg: &str| dump.lines().find(|l| l.trim_start().starts_with(arg));
+        assert!(line("--smart-case").is_none());
+        assert!(line("--ignore-case").unwrap().ends_with("command line"));
+        assert!(line("--line-number")
+            .unwrap()
+            .ends_with("/home/me/.minigreprc:2"));
+        assert!(line("--max-count=9").unwrap().ends_with("MINIGREP_OPTS"));
+
+        let rc_only = |arg: &str| parse(rc::parse_rc(rc, arg), None);
+        assert!(rc_only("-e foo").is_err());
+        assert!(rc_only("poem.txt").is_err());
+
+        // 配置文件最后的 --replace 不能拿命令行上的参数当值
+        let mut args = rc::parse_rc(rc, "-n\n--replace\n");
+        args.extend(cli.iter().map(|arg| (arg.to_string(), Source::CommandLine)));
+        match parse(args, None) {
+            Err(MinigrepError::Usage(message)) => assert_eq!(
+                "/home/me/.minigreprc:2: option '--replace' requires a value",
+                message
+            ),
+            _ => panic!("expected a usage error"),
+        }
+
+        // 命令行上的 --no-FLAG 撤销配置文件里的开关
+        let mut args = rc::parse_rc(rc, "-n\n--json\n--hidden\n--no-ignore\n-c\n");
+        let cli = [
+            "--no-line-number",
+            "--no-json",
+            "--ignore",
+            "--no-count",
+            "x",
+        ];
+        args.extend(cli.iter().map(|arg| (arg.to_string(), Source::CommandLine)));
+        let config = match parse(args, None).unwrap() {
+            Command::Search(config) => config,
+            _ => panic!("expected a search"),
+        };
+        assert!(!config.line_number && !config.json && !config.no_ignore);
+        assert!(config.hidden);
+        assert_eq!(OutputMode::Lines, config.mode);
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert!(matches!(parse_args(&["body", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parse_args(&["-V"]), Ok(Command::Version)));
+    }
+
+    #[test]
+    fn usage_errors() {
+        let err = |args: &[&str]| match parse_args(args) {
+            Err(MinigrepError::Usage(message)) => message,
+            Err(e) => panic!("expected a usage error, got {:?}", e),
+            Ok(_) => panic!("expected an error"),
+        };
+        assert_eq!("unknown option '--frobnicate'", err(&["--frobnicate"]));
+        assert_eq!("unknown option '-x'", err(&["-nx", "a", "b"]));
+        assert_eq!("option '--include' requires a value", err(&["--include"]));
+        assert_eq!(
+            "option '--regex' does not take a value",
+            err(&["--regex=yes"])
+        );
+        assert_eq!(
+            "invalid number 'many' for '--context'",
+            err(&["-C", "many"])
+        );
+        assert_eq!("missing PATTERN (see --help)", err(&["-n"]));
+    }
+}
@@ -0,0 +1,185 @@
+use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
+use std::io::{self, BufRead, BufReader, Read};
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+/// String::from_utf8_lossy(bytes) 里的字节偏移换回 bytes 里的偏移
+///
+/// 每段非法字节在解码后是一个 U+FFFD, 偏移都落在字符边界上, 对应那一段的开头或结尾
+pub fn raw_offset(bytes: &[u8], decoded: usize) -> usize {
+    let (mut raw, mut text) = (0, 0);
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if decoded <= text + valid {
+            return raw + decoded - text;
+        }
+        text += valid;
+        raw += valid;
+        if !chunk.invalid().is_empty() {
+            text += REPLACEMENT_CHARACTER.len_utf8();
+            raw += chunk.invalid().len();
+        }
+    }
+    raw
+}
+
+/// 根据开头的 BOM 决定怎么解码, 之后读到的总是 UTF-8
+///
+/// UTF-8 的 BOM 直接跳过, UTF-16 会被转码; 没有 BOM 时原样返回
+pub fn decode_bom<'a>(mut reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn BufRead + 'a>> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        Ok(reader)
+    } else if head.starts_with(UTF16LE_BOM) {
+        reader.consume(UTF16LE_BOM.len());
+        Ok(Box::new(BufReader::new(Utf16Reader::new(reader, false))))
+    } else if head.starts_with(UTF16BE_BOM) {
+        reader.consume(UTF16BE_BOM.len());
+        Ok(Box::new(BufReader::new(Utf16Reader::new(reader, true))))
+    } else {
+        Ok(reader)
+    }
+}
+
+/// 把 UTF-16 字节流转成 UTF-8 字节流
+///
+/// 不成对的代理项和末尾多出的单个字节都换成 U+FFFD, 不会报错
+pub struct Utf16Reader<R> {
+    inner: R,
+    big_endian: bool,
+    // 还没凑成完整字符的输入字节: 半个码元, 或者等待低位代理的高位代理
+    pending: Vec<u8>,
+    // 已经转好还没被取走的 UTF-8
+    decoded: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> Utf16Reader<R> {
+    pub fn new(inner: R, big_endian: bool) -> Utf16Reader<R> {
+        Utf16Reader {
+            inner,
+            big_endian,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        self.decoded.clear();
+        self.pos = 0;
+
+        let mut chunk = [0; 8 * 1024];
+        let n = self.inner.read(&mut chunk)?;
+        if n == 0 {
+            self.eof = true;
+        }
+        self.pending.extend_from_slice(&chunk[..n]);
+
+        let mut units: Vec<u16> = self
+            .pending
+            .chunks_exact(2)
+            .map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if self.big_endian {
+                    u16::from_be_bytes(pair)
+                } else {
+                    u16::from_le_bytes(pair)
+                }
+            })
+            .collect();
+        let mut keep = self.pending.len() % 2;
+        // 高位代理可能要和下一次读到的低位代理拼起来
+        if !self.eof && units.last().is_some_and(|u| (0xd800..0xdc00).contains(u)) {
+            units.pop();
+            keep += 2;
+        }
+        let used = self.pending.len() - keep;
+        self.pending.drain(..used);
+
+        let mut buf = [0; 4];
+        for c in decode_utf16(units) {
+            let c = c.unwrap_or(REPLACEMENT_CHARACTER);
+            self.decoded
+                .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        if self.eof && !self.pending.is_empty() {
+            self.pending.clear();
+            self.decoded
+                .extend_from_slice(REPLACEMENT_CHARACTER.encode_utf8(&mut buf).as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Utf16Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn decode(bytes: &[u8]) -> String {
+        let mut reader = decode_bom(Box::new(Cursor::new(bytes.to_vec()))).unwrap();
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        text
+    }
+
+    // 每次只读一个字节, 代理对和码元都会被拆开
+    struct OneByte<R>(R);
+
+    impl<R: Read> Read for OneByte<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn boms() {
+        assert_eq!("héllo\n", decode(b"\xef\xbb\xbfh\xc3\xa9llo\n"));
+        assert_eq!("hé\n", decode(b"\xff\xfeh\x00\xe9\x00\n\x00"));
+        assert_eq!("hé\n", decode(b"\xfe\xff\x00h\x00\xe9\x00\n"));
+        assert_eq!("plain", decode(b"plain"));
+    }
+
+    #[test]
+    fn surrogates_across_reads() {
+        // U+1F980 是 D83E DD80
+        let bytes = b"\x3e\xd8\x80\xdd!\x00\x3e\xd8";
+        let mut reader = Utf16Reader::new(OneByte(Cursor::new(bytes.to_vec())), false);
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!("\u{1f980}!\u{fffd}", text);
+    }
+
+    #[test]
+    fn offsets_before_lossy_decoding() {
+        let bytes = b"caf\xe9 ok \xff\xfex";
+        let decoded = String::from_utf8_lossy(bytes);
+        let ok = decoded.find("ok").unwrap();
+        assert_eq!((7, 5), (ok, raw_offset(bytes, ok)));
+        let x = decoded.find('x').unwrap();
+        assert_eq!(b'x', bytes[raw_offset(bytes, x)]);
+        assert_eq!(bytes.len(), raw_offset(bytes, decoded.len()));
+    }
+}
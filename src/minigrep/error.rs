@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// minigrep 所有可能出现的错误, main 根据种类决定退出码
+#[derive(Debug)]
+pub enum MinigrepError {
+    /// 命令行参数不对
+    Usage(String),
+    /// 读写失败, path 是出错的文件, 写标准输出失败时没有 path
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// 正则或者 --include / --exclude 的 glob 写错了
+    Pattern(String),
+    /// 文件不是合法的 UTF-8, 并且没有用 --lossy
+    Encoding { path: PathBuf, line: usize },
+    /// 有的文件没能搜索完, 每个错误出现时已经输出过了, 这里保留第一个
+    Partial {
+        failed: usize,
+        first: Box<MinigrepError>,
+    },
+}
+
+impl MinigrepError {
+    pub fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> MinigrepError {
+        MinigrepError::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// 写标准输出失败, 比如管道另一端已经关了, 继续搜索也没有意义
+    pub fn is_output_error(&self) -> bool {
+        matches!(self, MinigrepError::Io { path: None, .. })
+    }
+}
+
+impl fmt::Display for MinigrepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MinigrepError::Usage(message) | MinigrepError::Pattern(message) => {
+                write!(f, "{}", message)
+            }
+            MinigrepError::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {}", path.display(), source),
+            MinigrepError::Io { path: None, source } => write!(f, "{}", source),
+            MinigrepError::Encoding { path, line } => write!(
+                f,
+                "{}: line {}: stream did not contain valid UTF-8 (use --lossy to search it anyway)",
+                path.display(),
+                line
+            ),
+            MinigrepError::Partial { failed, .. } => {
+                let noun = if *failed == 1 { "file" } else { "files" };
+                write!(f, "{} {} could not be searched", failed, noun)
+            }
+        }
+    }
+}
+
+impl Error for MinigrepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MinigrepError::Io { source, .. } => Some(source),
+            MinigrepError::Partial { first, .. } => Some(first.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// 参数解析里的错误信息都是现拼的字符串
+impl From<String> for MinigrepError {
+    fn from(message: String) -> MinigrepError {
+        MinigrepError::Usage(message)
+    }
+}
+
+// 不知道是哪个文件的 I/O 错误, 实际上只有写标准输出
+impl From<io::Error> for MinigrepError {
+    fn from(source: io::Error) -> MinigrepError {
+        MinigrepError::Io { path: None, source }
+    }
+}
+
+impl From<regex::Error> for MinigrepError {
+    fn from(e: regex::Error) -> MinigrepError {
+        MinigrepError::Pattern(e.to_string())
+    }
+}
+
+impl From<glob::PatternError> for MinigrepError {
+    fn from(e: glob::PatternError) -> MinigrepError {
+        MinigrepError::Pattern(format!("invalid glob: {}", e))
+    }
+}
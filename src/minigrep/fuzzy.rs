@@ -0,0 +1,380 @@
+use crate::minigrep::encoding;
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::matcher::{fold, Submatch};
+use crate::minigrep::output::{Printer, Stats};
+use crate::minigrep::search::{for_each_line_bytes, BinaryFiles, SearchOptions};
+use std::borrow::Cow;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::str;
+
+// 打分的权重, 思路和 fzf 类似: 每个匹配的字符得分, 连续匹配和落在单词开头有奖励,
+// 中间跳过的字符扣分
+const SCORE_MATCH: i32 = 16;
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_CONSECUTIVE: i32 = 8;
+const PENALTY_GAP_START: i32 = 3;
+const PENALTY_GAP_EXTEND: i32 = 1;
+
+// 动态规划要回溯, 每个 (查询字符, 行字符) 组合记 4 个字节; 超过这么多组合的长行改用贪心匹配
+const MAX_CELLS: usize = 1 << 22;
+
+/// --fuzzy-threshold 的默认值
+pub const DEFAULT_THRESHOLD: u8 = 50;
+
+/// --fuzzy 的查询
+#[derive(Debug)]
+pub struct Fuzzy {
+    query: Vec<char>,
+    case_sensitive: bool,
+    /// 0 到 100, 低于它的行不输出
+    threshold: u8,
+}
+
+/// 一行的得分和匹配上的字符
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// 0 到 100, 100 表示整个查询连续地出现在单词开头
+    pub score: u8,
+    /// 匹配上的字符的字节范围, 相邻的已经合并
+    pub spans: Vec<Range<usize>>,
+}
+
+impl Fuzzy {
+    pub fn new(query: &str, case_sensitive: bool, threshold: u8) -> Fuzzy {
+        let fold_case = |c| if case_sensitive { c } else { fold(c) };
+        Fuzzy {
+            query: query.chars().map(fold_case).collect(),
+            case_sensitive,
+            threshold,
+        }
+    }
+
+    /// 给一行打分, 分数低于阈值时返回 None
+    pub fn score(&self, line: &str) -> Option<FuzzyMatch> {
+        if self.query.is_empty() {
+            return None;
+        }
+        let chars: Vec<(usize, char)> = line
+            .char_indices()
+            .map(|(i, c)| (i, if self.case_sensitive { c } else { fold(c) }))
+            .collect();
+        let (score, positions) = self
+            .subsequence(&chars)
+            .or_else(|| self.with_typos(&chars))?;
+
+        let n = self.query.len() as i32;
+        let best = n * (SCORE_MATCH + BONUS_CONSECUTIVE);
+        let score = (score.max(0) * 100 / best).min(100) as u8;
+        if score < self.threshold {
+            return None;
+        }
+
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for j in positions {
+            // chars 里的是折叠后的字符, 长度要按原来的算
+            let start = chars[j].0;
+            let end = start + line[start..].chars().next().map_or(1, char::len_utf8);
+            match spans.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => spans.push(start..end),
+            }
+        }
+        Some(FuzzyMatch { score, spans })
+    }
+
+    // 查询按顺序出现在行里 (可以不连续) 时, 用动态规划找得分最高的对齐方式
+    fn subsequence(&self, chars: &[(usize, char)]) -> Option<(i32, Vec<usize>)> {
+        let (n, m) = (self.query.len(), chars.len());
+        if m < n {
+            return None;
+        }
+        if n * m > MAX_CELLS {
+            return self.greedy(chars);
+        }
+        // prev[j] / row[j]: query[..=i - 1] / query[..=i] 已经匹配,
+        // 并且最后一个查询字符匹配在 chars[j] 上的最高分
+        // from[i * m + j]: 这时 query[i - 1] 匹配在哪, 用来回溯
+        let mut prev: Vec<Option<i32>> = vec![None; m];
+        let mut row: Vec<Option<i32>> = vec![None; m];
+        let mut from = vec![0u32; n * m];
+        for i in 0..n {
+            // carry: 上一行在 j - 1 之前结束, 再跳过若干字符到达 j 的最好得分
+            let mut carry: Option<(i32, usize)> = None;
+            for j in 0..m {
+                row[j] = None;
+                if i > 0 && j >= 2 {
+                    carry = carry.map(|(s, k)| (s - PENALTY_GAP_EXTEND, k));
+                    if let Some(s) = prev[j - 2] {
+                        let s = s - PENALTY_GAP_START;
+                        if carry.is_none_or(|(c, _)| s > c) {
+                            carry = Some((s, j - 2));
+                        }
+                    }
+                }
+                if chars[j].1 != self.query[i] {
+                    continue;
+                }
+                let base = SCORE_MATCH
+                    + if is_boundary(chars, j) {
+                        BONUS_BOUNDARY
+                    } else {
+                        0
+                    };
+                if i == 0 {
+                    row[j] = Some(base);
+                    continue;
+                }
+                let consecutive = if j > 0 {
+                    prev[j - 1].map(|s| (s + BONUS_CONSECUTIVE, j - 1))
+                } else {
+                    None
+                };
+                let previous = match (consecutive, carry) {
+                    (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                    (a, b) => a.or(b),
+                };
+                if let Some((s, k)) = previous {
+                    row[j] = Some(base + s);
+                    from[i * m + j] = k as u32;
+                }
+            }
+            std::mem::swap(&mut prev, &mut row);
+        }
+
+        let (mut j, best) = (0..m)
+            .filter_map(|j| prev[j].map(|s| (j, s)))
+            .max_by_key(|&(j, s)| (s, std::cmp::Reverse(j)))?;
+        let mut positions = vec![0; n];
+        for i in (0..n).rev() {
+            positions[i] = j;
+            j = from[i * m + j] as usize;
+        }
+        Some((best, positions))
+    }
+
+    // 和 fzf 处理长输入的办法一样: 先从前往后找到第一次完整出现的结尾,
+    // 再从结尾往回找, 让匹配尽量紧凑. 不一定是得分最高的对齐方式
+    fn greedy(&self, chars: &[(usize, char)]) -> Option<(i32, Vec<usize>)> {
+        let n = self.query.len();
+        let mut i = 0;
+        let end = chars.iter().position(|&(_, c)| {
+            if c == self.query[i] {
+                i += 1;
+            }
+            i == n
+        })?;
+        let mut positions = vec![0; n];
+        for j in (0..=end).rev() {
+            if chars[j].1 == self.query[i - 1] {
+                i -= 1;
+                positions[i] = j;
+                if i == 0 {
+                    break;
+                }
+            }
+        }
+
+        // 按和动态规划一样的规则打分
+        let mut score = 0;
+        for (k, &j) in positions.iter().enumerate() {
+            score += SCORE_MATCH;
+            if is_boundary(chars, j) {
+                score += BONUS_BOUNDARY;
+            }
+            if k > 0 {
+                let gap = (j - positions[k - 1] - 1) as i32;
+                score += if gap == 0 {
+                    BONUS_CONSECUTIVE
+                } else {
+                    -PENALTY_GAP_START - PENALTY_GAP_EXTEND * (gap - 1)
+                };
+            }
+        }
+        Some((score, positions))
+    }
+
+    // 不是子序列时允许少量的拼写错误: 找编辑距离最小的子串, 错一个字符扣掉一个字符的分
+    fn with_typos(&self, chars: &[(usize, char)]) -> Option<(i32, Vec<usize>)> {
+        let n = self.query.len();
+        let max_typos = n / 4;
+        if max_typos == 0 {
+            return None;
+        }
+        // 只保留两行: 编辑距离, 以及这个子串从哪里开始
+        let mut prev: Vec<(usize, usize)> = (0..=chars.len()).map(|j| (0, j)).collect();
+        for i in 1..=n {
+            let mut row = vec![(i, 0); chars.len() + 1];
+            for j in 1..=chars.len() {
+                let cost = usize::from(chars[j - 1].1 != self.query[i - 1]);
+                let diagonal = (prev[j - 1].0 + cost, prev[j - 1].1);
+                let up = (prev[j].0 + 1, prev[j].1);
+                let left = (row[j - 1].0 + 1, row[j - 1].1);
+                row[j] = diagonal.min(up).min(left);
+            }
+            prev = row;
+        }
+        let (end, &(distance, start)) = prev
+            .iter()
+            .enumerate()
+            .skip(1)
+            // 距离相同时选长度和查询最接近的, "somebodu" 应该高亮整个 "somebody"
+            .min_by_key(|&(end, &(d, start))| (d, (end - start).abs_diff(n)))?;
+        if distance > max_typos || start >= end {
+            return None;
+        }
+        let score = (n - distance) as i32 * SCORE_MATCH;
+        Some((score, (start..end).collect()))
+    }
+}
+
+// 行首, 或者前一个字符不是字母数字时, 算单词开头
+fn is_boundary(chars: &[(usize, char)], j: usize) -> bool {
+    j == 0 || !chars[j - 1].1.is_alphanumeric()
+}
+
+/// 给 reader 里的每一行打分, 按得分从高到低输出, 得分相同时按行号
+///
+/// 排序需要先看完整个文件, 所以超过阈值的行会先留在内存里.
+/// -m 取的是得分最高的前几行; 二进制文件和普通搜索一样按 options.binary 处理
+pub fn search_reader<R: BufRead, W: Write>(
+    fuzzy: &Fuzzy,
+    path: &Path,
+    mut reader: R,
+    options: &SearchOptions,
+    printer: &mut Printer<W>,
+) -> Result<Stats, MinigrepError> {
+    let read_error = |e| MinigrepError::io(path, e);
+    printer.begin_file(path);
+    let mut binary = reader.fill_buf().map_err(read_error)?.contains(&0);
+    if binary {
+        match options.binary {
+            BinaryFiles::WithoutMatch => return Ok(printer.end_file()?),
+            BinaryFiles::Binary => printer.set_binary(),
+            BinaryFiles::Text => {}
+        }
+    }
+
+    let mut ranked = Vec::new();
+    let mut failure = None;
+    for_each_line_bytes(reader, |line_number, offset, bytes| {
+        if !binary && bytes.contains(&0) {
+            binary = true;
+            match options.binary {
+                BinaryFiles::WithoutMatch => return Ok(false),
+                BinaryFiles::Binary => printer.set_binary(),
+                BinaryFiles::Text => {}
+            }
+        }
+        let line = if binary || options.lossy {
+            String::from_utf8_lossy(bytes)
+        } else {
+            match str::from_utf8(bytes) {
+                Ok(line) => Cow::Borrowed(line),
+                Err(_) => {
+                    failure = Some(MinigrepError::Encoding {
+                        path: path.to_path_buf(),
+                        line: line_number,
+                    });
+                    return Ok(false);
+                }
+            }
+        };
+        if let Some(mut m) = fuzzy.score(&line) {
+            // printer 要的是原始字节里的位置
+            if let Cow::Owned(_) = line {
+                for span in &mut m.spans {
+                    *span = encoding::raw_offset(bytes, span.start)
+                        ..encoding::raw_offset(bytes, span.end);
+                }
+            }
+            ranked.push((m.score, line_number, offset, bytes.to_vec(), m.spans));
+        }
+        Ok(true)
+    })
+    .map_err(read_error)?;
+    if let Some(e) = failure {
+        printer.end_file()?;
+        return Err(e);
+    }
+
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let limit = options.max_count.unwrap_or(usize::MAX);
+    for (_, line_number, offset, line, spans) in ranked.into_iter().take(limit) {
+        let matches: Vec<Submatch> = spans
+            .into_iter()
+            .map(|range| Submatch { range, pattern: 0 })
+            .collect();
+        printer.line(line_number, offset, &line, true, &matches)?;
+    }
+    Ok(printer.end_file()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minigrep::output::PrintOptions;
+    use std::io::Cursor;
+
+    fn score(query: &str, line: &str) -> Option<FuzzyMatch> {
+        Fuzzy::new(query, false, 0).score(line)
+    }
+
+    #[test]
+    fn exact_word_scores_highest() {
+        let m = score("frog", "How public, like a frog").unwrap();
+        assert_eq!(100, m.score);
+        assert_eq!(vec![19..23], m.spans);
+        assert!(score("frog", "f r o g").unwrap().score < 100);
+        assert!(score("xyz", "frog").is_none());
+    }
+
+    #[test]
+    fn prefers_consecutive_and_boundaries() {
+        // "pblc" 在 public 里, 开头在单词边界上
+        let m = score("pblc", "How public").unwrap();
+        assert_eq!(vec![4..5, 6..8, 9..10], m.spans);
+        let tight = score("dre", "dreary").unwrap().score;
+        let loose = score("dre", "daredevil").unwrap().score;
+        assert!(tight > loose, "{} <= {}", tight, loose);
+    }
+
+    #[test]
+    fn tolerates_typos() {
+        let m = score("somebodu", "How dreary to be somebody!").unwrap();
+        assert_eq!(vec![17..25], m.spans);
+        assert!(Fuzzy::new("somebodu", false, 90)
+            .score("somebody")
+            .is_none());
+        assert!(score("ΟΔΟΣ", "οδος").is_some());
+    }
+
+    #[test]
+    fn long_lines_use_greedy_match() {
+        let line = format!("{}frog", "x".repeat(MAX_CELLS));
+        let m = score("frog", &line).unwrap();
+        assert_eq!(vec![MAX_CELLS..MAX_CELLS + 4], m.spans);
+        // 从结尾往回找, 不会用上开头那个离得很远的 a
+        let m = score("ab", &format!("a{}ab", "x".repeat(MAX_CELLS))).unwrap();
+        assert_eq!(vec![MAX_CELLS + 1..MAX_CELLS + 3], m.spans);
+    }
+
+    #[test]
+    fn binary_files_follow_options() {
+        let output = |binary| {
+            let options = SearchOptions {
+                binary,
+                ..SearchOptions::default()
+            };
+            let mut printer = Printer::new(Vec::new(), PrintOptions::default());
+            let input = Cursor::new(b"frog\0\nfrog\n".to_vec());
+            let fuzzy = Fuzzy::new("frog", false, 0);
+            search_reader(&fuzzy, Path::new("f"), input, &options, &mut printer).unwrap();
+            String::from_utf8(printer.into_inner()).unwrap()
+        };
+        assert_eq!("Binary file f matches\n", output(BinaryFiles::Binary));
+        assert_eq!("frog\0\nfrog\n", output(BinaryFiles::Text));
+        assert_eq!("", output(BinaryFiles::WithoutMatch));
+    }
+}
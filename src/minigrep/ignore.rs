@@ -0,0 +1,233 @@
+use glob::{MatchOptions, Pattern};
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 目录里会被读取的忽略文件, 后面的优先级更高
+pub const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+// gitignore 里的 `*` 不能跨过 `/`, `**` 才可以
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// 一个 .gitignore (或 .ignore) 文件里的规则, 只作用于它所在的目录及其子目录
+pub struct Gitignore {
+    dir: PathBuf,
+    // 规则来自 dir 上层的目录时, dir 相对于那个目录的路径, 带 `/` 的规则要加上它再匹配
+    prefix: PathBuf,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: Pattern,
+    // `!` 开头: 重新包含之前被忽略的路径
+    negated: bool,
+    // `/` 结尾: 只匹配目录
+    dir_only: bool,
+    // 开头或中间有 `/`: 相对于 dir 匹配完整路径, 否则在任意层级匹配文件名
+    anchored: bool,
+}
+
+impl Gitignore {
+    /// 读取 dir 下名为 name 的忽略文件, 文件不存在时返回 None
+    pub fn from_dir(dir: &Path, name: &str) -> io::Result<Option<Gitignore>> {
+        match fs::read_to_string(dir.join(name)) {
+            Ok(contents) => Ok(Some(Gitignore::parse(dir, &contents))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 写错的模式和 git 一样直接跳过
+    pub fn parse(dir: &Path, contents: &str) -> Gitignore {
+        let rules = contents.lines().filter_map(parse_rule).collect();
+        Gitignore {
+            dir: dir.to_path_buf(),
+            prefix: PathBuf::new(),
+            rules,
+        }
+    }
+
+    /// Some(true) 表示忽略, Some(false) 表示被 `!` 重新包含, None 表示没有规则匹配
+    ///
+    /// 同一个文件里后面的规则覆盖前面的
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let name = Path::new(relative.file_name()?);
+        let anchored = if self.prefix.as_os_str().is_empty() {
+            Cow::Borrowed(relative)
+        } else {
+            Cow::Owned(self.prefix.join(relative))
+        };
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| {
+                let target = if rule.anchored { &*anchored } else { name };
+                rule.pattern.matches_path_with(target, MATCH_OPTIONS)
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// dir 上层目录里的忽略文件, 从仓库顶层 (有 .git 的目录) 开始, 按从外到内的顺序
+///
+/// 返回的规则用在 dir 下面的路径上, 和在仓库顶层搜索时的结果一样.
+/// dir 不在 git 仓库里时不去读上层目录, 以免用上家目录之类的无关规则
+pub fn for_ancestors(dir: &Path) -> io::Result<Vec<Gitignore>> {
+    let canonical = dir.canonicalize()?;
+    let top = match canonical
+        .ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+    {
+        Some(top) => top,
+        None => return Ok(Vec::new()),
+    };
+    let mut stack = Vec::new();
+    for ancestor in canonical.ancestors().skip(1) {
+        // 从里往外读, 读到顶层为止
+        if !ancestor.starts_with(top) {
+            break;
+        }
+        for name in IGNORE_FILES.iter().rev() {
+            if let Some(mut gitignore) = Gitignore::from_dir(ancestor, name)? {
+                gitignore.dir = dir.to_path_buf();
+                gitignore.prefix = canonical
+                    .strip_prefix(ancestor)
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf();
+                stack.push(gitignore);
+            }
+        }
+    }
+    stack.reverse();
+    Ok(stack)
+}
+
+/// 按从外到内的顺序保存的忽略规则, 越靠后 (越深的目录) 优先级越高
+pub fn is_ignored(stack: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    stack
+        .iter()
+        .rev()
+        .find_map(|gitignore| gitignore.matched(path, is_dir))
+        .unwrap_or(false)
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    if line.starts_with('#') {
+        return None;
+    }
+    // 结尾的空格被忽略, 除非用 `\ ` 转义
+    let mut line = line;
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    if line.is_empty() {
+        return None;
+    }
+
+    // gitignore 用反斜杠转义, glob 用 [] 转义
+    let mut glob = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    glob.push_str(&Pattern::escape(escaped.encode_utf8(&mut [0; 4])));
+                }
+            }
+            _ => glob.push(c),
+        }
+    }
+    let pattern = Pattern::new(&glob).ok()?;
+    Some(Rule {
+        pattern,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(gitignore: &Gitignore, path: &str, is_dir: bool) -> Option<bool> {
+        gitignore.matched(&Path::new("repo").join(path), is_dir)
+    }
+
+    #[test]
+    fn rules() {
+        let gitignore = Gitignore::parse(
+            Path::new("repo"),
+            "# comment\n\
+             *.log\n\
+             !keep.log\n\
+             /target\n\
+             build/\n\
+             docs/**/*.html\n\
+             \\#notes  \n",
+        );
+        assert_eq!(Some(true), ignored(&gitignore, "a/b/debug.log", false));
+        assert_eq!(Some(false), ignored(&gitignore, "a/keep.log", false));
+        // 以 / 开头的只匹配这一层
+        assert_eq!(Some(true), ignored(&gitignore, "target", true));
+        assert_eq!(None, ignored(&gitignore, "src/target", true));
+        // 以 / 结尾的只匹配目录
+        assert_eq!(Some(true), ignored(&gitignore, "src/build", true));
+        assert_eq!(None, ignored(&gitignore, "src/build", false));
+        assert_eq!(
+            Some(true),
+            ignored(&gitignore, "docs/api/v1/index.html", false)
+        );
+        assert_eq!(None, ignored(&gitignore, "src/docs/index.html", false));
+        assert_eq!(Some(true), ignored(&gitignore, "#notes", false));
+    }
+
+    #[test]
+    fn deeper_files_win() {
+        let stack = vec![
+            Gitignore::parse(Path::new("repo"), "*.txt\n"),
+            Gitignore::parse(Path::new("repo/docs"), "!*.txt\n"),
+        ];
+        assert!(is_ignored(&stack, Path::new("repo/notes.txt"), false));
+        assert!(!is_ignored(&stack, Path::new("repo/docs/guide.txt"), false));
+        assert!(!is_ignored(&stack, Path::new("repo/main.rs"), false));
+    }
+
+    #[test]
+    fn rules_from_ancestors() {
+        let top = std::env::temp_dir().join(format!("minigrep-ignore-{}", std::process::id()));
+        fs::create_dir_all(top.join(".git")).unwrap();
+        fs::create_dir_all(top.join("src/nested")).unwrap();
+        fs::write(top.join(".gitignore"), "*.log\n/src/nested/\n").unwrap();
+        fs::write(top.join("src/.ignore"), "!keep.log\n").unwrap();
+
+        let dir = top.join("src");
+        let stack = for_ancestors(&dir).unwrap();
+        assert_eq!(1, stack.len());
+        assert!(is_ignored(&stack, &dir.join("debug.log"), false));
+        // 带 / 的规则相对于顶层目录
+        assert!(is_ignored(&stack, &dir.join("nested"), true));
+        // src 自己的 .ignore 由 walk 读, 不在这里
+        let stack = for_ancestors(&dir.join("nested")).unwrap();
+        assert_eq!(2, stack.len());
+        assert!(!is_ignored(&stack, &dir.join("nested/keep.log"), false));
+        fs::remove_dir_all(&top).unwrap();
+    }
+}
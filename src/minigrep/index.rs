@@ -0,0 +1,549 @@
+use crate::minigrep::encoding;
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::walk::{self, FileFilter};
+use crate::MatchMode;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+/// 索引文件放在被索引的目录下, 以 . 开头, 默认不会被搜索
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+// 版本 2 起 UTF-16 文件按解码后的 UTF-8 建索引, 旧的索引要重建
+const MAGIC: &[u8] = b"minigrep-index 2\n";
+
+/// 目录下文件的三字母组 (trigram) 倒排索引
+///
+/// 文件内容 (和搜索时一样去掉 BOM, UTF-16 先转成 UTF-8) 按字节取所有连续的三个字节,
+/// ASCII 字母统一成小写, 这样一个索引同时能用于区分和不区分大小写的搜索.
+/// 每个三字母组对应包含它的文件编号列表
+#[derive(Debug, Default)]
+pub struct Index {
+    files: Vec<IndexedFile>,
+    postings: BTreeMap<u32, Vec<u32>>,
+}
+
+// 文件的路径 (相对于索引所在的目录), 以及建索引时的修改时间和大小, 用来判断是否过期
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IndexedFile {
+    path: String,
+    mtime: (u64, u32),
+    size: u64,
+}
+
+/// index build 的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    pub files: usize,
+    /// 新增或者修改过, 重新读取的文件
+    pub updated: usize,
+    /// 上次索引之后被删掉的文件
+    pub removed: usize,
+}
+
+impl IndexedFile {
+    fn stat(path: String, metadata: &fs::Metadata) -> io::Result<IndexedFile> {
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(IndexedFile {
+            path,
+            mtime: (mtime.as_secs(), mtime.subsec_nanos()),
+            size: metadata.len(),
+        })
+    }
+}
+
+impl Index {
+    /// 读取 dir 下的索引, 没有建过索引时返回 None
+    pub fn open(dir: &Path) -> Result<Option<Index>, MinigrepError> {
+        let path = dir.join(INDEX_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(MinigrepError::io(path, e)),
+        };
+        Index::read(&mut BufReader::new(file))
+            .map(Some)
+            .map_err(|e| MinigrepError::io(path, e))
+    }
+
+    // 格式: MAGIC, 文件数, 每个文件 (路径, mtime 秒, 纳秒, 大小),
+    // 三字母组数, 每个三字母组 (值, 文件数, 文件编号...), 整数都是小端
+    fn read<R: Read>(reader: &mut R) -> io::Result<Index> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid(
+                "not a minigrep index (rebuild it with 'minigrep index build')",
+            ));
+        }
+        let mut index = Index::default();
+        for _ in 0..read_u32(reader)? {
+            let mut path = vec![0; read_u32(reader)? as usize];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| invalid("invalid path in index"))?;
+            let mtime = (read_u64(reader)?, read_u32(reader)?);
+            let size = read_u64(reader)?;
+            index.files.push(IndexedFile { path, mtime, size });
+        }
+        for _ in 0..read_u32(reader)? {
+            let trigram = read_u32(reader)?;
+            let ids = (0..read_u32(reader)?)
+                .map(|_| read_u32(reader))
+                .collect::<io::Result<Vec<u32>>>()?;
+            if ids.iter().any(|&id| id as usize >= index.files.len()) {
+                return Err(invalid("file number out of range in index"));
+            }
+            index.postings.insert(trigram, ids);
+        }
+        Ok(index)
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&(self.files.len() as u32).to_le_bytes())?;
+        for file in &self.files {
+            out.write_all(&(file.path.len() as u32).to_le_bytes())?;
+            out.write_all(file.path.as_bytes())?;
+            out.write_all(&file.mtime.0.to_le_bytes())?;
+            out.write_all(&file.mtime.1.to_le_bytes())?;
+            out.write_all(&file.size.to_le_bytes())?;
+        }
+        out.write_all(&(self.postings.len() as u32).to_le_bytes())?;
+        for (trigram, ids) in &self.postings {
+            out.write_all(&trigram.to_le_bytes())?;
+            out.write_all(&(ids.len() as u32).to_le_bytes())?;
+            for id in ids {
+                out.write_all(&id.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+
+    // 把倒排表转回每个文件各自的三字母组, 增量更新时没变的文件直接沿用
+    fn trigrams_by_file(&self) -> Vec<Vec<u32>> {
+        let mut by_file = vec![Vec::new(); self.files.len()];
+        for (&trigram, ids) in &self.postings {
+            for &id in ids {
+                by_file[id as usize].push(trigram);
+            }
+        }
+        by_file
+    }
+
+    // 含有所有这些三字母组的文件
+    fn files_with_all(&self, trigrams: &[u32]) -> HashSet<u32> {
+        let mut lists: Vec<&[u32]> = Vec::with_capacity(trigrams.len());
+        for trigram in trigrams {
+            match self.postings.get(trigram) {
+                Some(ids) => lists.push(ids),
+                None => return HashSet::new(),
+            }
+        }
+        // 从最短的列表开始求交集
+        lists.sort_by_key(|ids| ids.len());
+        let mut result: HashSet<u32> = lists[0].iter().copied().collect();
+        for ids in &lists[1..] {
+            result.retain(|id| ids.binary_search(id).is_ok());
+        }
+        result
+    }
+}
+
+/// 给 dir 建索引 (或者更新已有的索引), 修改时间和大小都没变的文件不会重新读取
+///
+/// 要索引的文件和递归搜索时一样: 跳过隐藏文件和被 .gitignore / .ignore 忽略的路径.
+/// 读不了的目录放进 skipped, 里面的文件不在索引里, 搜索时总是会被当作候选
+pub fn build_index(
+    dir: &Path,
+    skipped: &mut Vec<MinigrepError>,
+) -> Result<IndexStats, MinigrepError> {
+    if !dir.is_dir() {
+        return Err(MinigrepError::Usage(format!(
+            "{}: not a directory",
+            dir.display()
+        )));
+    }
+    let old = Index::open(dir)?.unwrap_or_default();
+    let mut old_trigrams = old.trigrams_by_file();
+    let old_ids: HashMap<&str, usize> = old
+        .files
+        .iter()
+        .enumerate()
+        .map(|(id, file)| (file.path.as_str(), id))
+        .collect();
+
+    let filter = FileFilter::new(&[], &[])?;
+    let mut stats = IndexStats::default();
+    let mut index = Index::default();
+    let mut still_there = 0;
+    for path in walk::collect_files(&[dir.to_path_buf()], &filter, skipped) {
+        // 不是 UTF-8 的路径不进索引, 搜索时总是会被当作候选
+        let relative = match path.strip_prefix(dir).ok().and_then(Path::to_str) {
+            Some(relative) => relative.to_string(),
+            None => continue,
+        };
+        let io_error = |e| MinigrepError::io(&path, e);
+        let file = IndexedFile::stat(relative, &fs::metadata(&path).map_err(io_error)?)
+            .map_err(io_error)?;
+        let old_id = old_ids.get(file.path.as_str()).copied();
+        if old_id.is_some() {
+            still_there += 1;
+        }
+        let trigrams = match old_id {
+            Some(id) if old.files[id] == file => std::mem::take(&mut old_trigrams[id]),
+            _ => {
+                stats.updated += 1;
+                file_trigrams(&path).map_err(io_error)?
+            }
+        };
+        let id = index.files.len() as u32;
+        for trigram in trigrams {
+            index.postings.entry(trigram).or_default().push(id);
+        }
+        index.files.push(file);
+    }
+    stats.files = index.files.len();
+    stats.removed = old.files.len() - still_there;
+
+    // 先写临时文件再 rename, 写到一半出错不会留下坏掉的索引
+    let path = dir.join(INDEX_FILE);
+    let tmp = dir.join(format!("{}.tmp-{}", INDEX_FILE, process::id()));
+    let written = File::create(&tmp)
+        .and_then(|file| index.write(&mut BufWriter::new(file)))
+        .and_then(|_| fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(MinigrepError::io(path, e));
+    }
+    Ok(stats)
+}
+
+/// 用 paths 下的索引去掉一定不会匹配的文件, 留下的文件仍然要正常搜索一遍
+///
+/// 命令行上直接给出的文件, 索引之后新建或修改过的文件, 以及用不上索引的
+/// 模式 (太短, 或者正则里找不到一定会出现的字面量) 都会保留
+pub fn narrow(
+    paths: &[PathBuf],
+    files: Vec<PathBuf>,
+    patterns: &[String],
+    mode: MatchMode,
+    case_sensitive: bool,
+) -> Result<Vec<PathBuf>, MinigrepError> {
+    let mut indexes = Vec::new();
+    for dir in paths.iter().filter(|path| path.is_dir()) {
+        let index = Index::open(dir)?.ok_or_else(|| {
+            MinigrepError::Usage(format!(
+                "{} has no index, run 'minigrep index build {}' first",
+                dir.display(),
+                dir.display()
+            ))
+        })?;
+        // 任意一个模式可能匹配的文件; None 表示哪个文件都有可能
+        let mut candidates = Some(HashSet::new());
+        for pattern in patterns {
+            match required_trigrams(pattern, mode, case_sensitive) {
+                Some(trigrams) => {
+                    if let Some(candidates) = &mut candidates {
+                        candidates.extend(index.files_with_all(&trigrams));
+                    }
+                }
+                None => candidates = None,
+            }
+        }
+        let candidates = candidates.map(|set| {
+            set.into_iter()
+                .map(|id| index.files[id as usize].path.clone())
+                .collect::<HashSet<String>>()
+        });
+        let indexed: HashMap<String, IndexedFile> = index
+            .files
+            .into_iter()
+            .map(|file| (file.path.clone(), file))
+            .collect();
+        indexes.push((dir, indexed, candidates));
+    }
+
+    let mut narrowed = Vec::with_capacity(files.len());
+    'files: for path in files {
+        if paths.contains(&path) {
+            narrowed.push(path);
+            continue;
+        }
+        for (dir, indexed, candidates) in &indexes {
+            let relative = match path.strip_prefix(dir).ok().and_then(Path::to_str) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            let candidates = match candidates {
+                Some(candidates) => candidates,
+                None => break,
+            };
+            if candidates.contains(relative) {
+                break;
+            }
+            // 不在索引里, 或者索引之后改过的文件只能照常搜索
+            let fresh = indexed.get(relative).is_some_and(|indexed| {
+                fs::metadata(&path)
+                    .and_then(|metadata| IndexedFile::stat(relative.to_string(), &metadata))
+                    .is_ok_and(|file| file == *indexed)
+            });
+            if fresh {
+                continue 'files;
+            }
+            break;
+        }
+        narrowed.push(path);
+    }
+    Ok(narrowed)
+}
+
+// 匹配的行里一定会出现的三字母组, 找不到 (不能用索引排除任何文件) 时返回 None
+fn required_trigrams(pattern: &str, mode: MatchMode, case_sensitive: bool) -> Option<Vec<u32>> {
+    let literals = match mode {
+        MatchMode::Literal => vec![pattern.to_string()],
+        MatchMode::Regex => regex_literals(pattern)?,
+    };
+    let mut trigrams: Vec<u32> = literals
+        .iter()
+        .flat_map(|literal| literal.as_bytes().windows(3))
+        // 不区分大小写时非 ASCII 字符的折叠和按字节转小写对不上;
+        // k 和 s 还分别和开尔文符号 K, 长 s ſ 折叠成同一个字符
+        .filter(|window| {
+            case_sensitive
+                || window
+                    .iter()
+                    .all(|b| b.is_ascii() && !matches!(b.to_ascii_lowercase(), b'k' | b's'))
+        })
+        .map(trigram)
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    if trigrams.is_empty() {
+        None
+    } else {
+        Some(trigrams)
+    }
+}
+
+// 正则里任何匹配都一定包含的字面量片段, 拿不准的写法直接返回 None
+//
+// 只看最外层 (不在分组里) 的连续普通字符, 遇到元字符就断开; 后面跟着
+// ? * {} 的字符可能不出现, 从片段里去掉. 有 | 或者 (?flags) 时整个放弃
+fn regex_literals(pattern: &str) -> Option<Vec<String>> {
+    if pattern.contains('|') || pattern.contains("(?") {
+        return None;
+    }
+    let mut literals = Vec::new();
+    let mut run = String::new();
+    let mut depth = 0;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                'd' | 'D' | 'w' | 'W' | 's' | 'S' | 'b' | 'B' => {
+                    literals.push(std::mem::take(&mut run));
+                }
+                // \x41, \p{L}, \n 之类, 不去逐个解释了
+                escaped if escaped.is_alphanumeric() => return None,
+                escaped if depth == 0 => run.push(escaped),
+                _ => {}
+            },
+            '?' | '*' | '{' => {
+                run.pop();
+                literals.push(std::mem::take(&mut run));
+                if c == '{' {
+                    chars.find(|&c| c == '}')?;
+                }
+            }
+            '[' => {
+                literals.push(std::mem::take(&mut run));
+                // 跳过字符类, 开头的 ] (或者 ^]) 是普通字符
+                let mut class = String::new();
+                loop {
+                    match chars.next()? {
+                        '\\' => {
+                            chars.next()?;
+                        }
+                        ']' if !class.is_empty() && class != "^" => break,
+                        c => class.push(c),
+                    }
+                }
+            }
+            '(' => {
+                literals.push(std::mem::take(&mut run));
+                depth += 1;
+            }
+            ')' => {
+                literals.push(std::mem::take(&mut run));
+                depth -= 1;
+            }
+            '+' | '.' | '^' | '$' => literals.push(std::mem::take(&mut run)),
+            c if depth == 0 => run.push(c),
+            _ => {}
+        }
+    }
+    literals.push(run);
+    literals.retain(|literal| !literal.is_empty());
+    Some(literals)
+}
+
+fn trigram(window: &[u8]) -> u32 {
+    let [a, b, c] = [window[0], window[1], window[2]].map(|b| b.to_ascii_lowercase());
+    u32::from_be_bytes([0, a, b, c])
+}
+
+// 边读边算, 不用把整个文件读进内存, 块与块之间保留最后两个字节
+//
+// 读的是搜索时看到的内容: 带 BOM 的 UTF-16 文件按原始字节算出的三字母组
+// 和模式对不上, 这个文件就会被错误地排除
+fn file_trigrams(path: &Path) -> io::Result<Vec<u32>> {
+    let mut file = encoding::decode_bom(Box::new(BufReader::new(File::open(path)?)))?;
+    let mut seen = HashSet::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut carry = 0;
+    loop {
+        let n = match file.read(&mut buf[carry..]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let end = carry + n;
+        seen.extend(buf[..end].windows(3).map(trigram));
+        carry = end.min(2);
+        buf.copy_within(end - carry..end, 0);
+    }
+    Ok(seen.into_iter().collect())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn literals_from_regex() {
+        let literals = |pattern| regex_literals(pattern);
+        assert_eq!(
+            Some(vec!["fn ".to_string(), "_main()".to_string()]),
+            literals(r"fn \w+_main\(\)")
+        );
+        assert_eq!(
+            Some(vec!["colo".to_string(), "r".to_string()]),
+            literals("colou?r")
+        );
+        assert_eq!(Some(vec!["ab".to_string()]), literals("(foo)?[]x]ab"));
+        assert_eq!(None, literals("foo|bar"));
+        assert_eq!(None, literals(r"\x41bc"));
+        // 太短的片段没有三字母组, 不能用来排除文件
+        assert_eq!(None, required_trigrams("ab?c", MatchMode::Regex, true));
+        assert_eq!(
+            2,
+            required_trigrams("Rust", MatchMode::Literal, true)
+                .unwrap()
+                .len()
+        );
+        assert_eq!(None, required_trigrams("Rust", MatchMode::Literal, false));
+    }
+
+    #[test]
+    fn build_update_and_narrow() {
+        let dir = env::temp_dir().join(format!("minigrep-index-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "pub fn frobnicate() {}\n").unwrap();
+        fs::write(
+            dir.join("src/main.rs"),
+            "fn main() { println!(\"Hello\") }\n",
+        )
+        .unwrap();
+        fs::write(dir.join("README"), "a frog\n").unwrap();
+        let utf16: Vec<u8> = "\u{feff}tadpole\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        fs::write(dir.join("u16.txt"), utf16).unwrap();
+
+        let stats = build_index(&dir, &mut Vec::new()).unwrap();
+        assert_eq!((4, 4, 0), (stats.files, stats.updated, stats.removed));
+        let walked = |dir: &Path| {
+            walk::collect_files(
+                &[dir.to_path_buf()],
+                &FileFilter::new(&[], &[]).unwrap(),
+                &mut Vec::new(),
+            )
+        };
+        let narrow = |pattern: &str, mode, case_sensitive| {
+            narrow(
+                &[dir.clone()],
+                walked(&dir),
+                &[pattern.to_string()],
+                mode,
+                case_sensitive,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            vec![dir.join("src/lib.rs")],
+            narrow("frob", MatchMode::Literal, true)
+        );
+        assert_eq!(
+            vec![dir.join("src/main.rs")],
+            narrow("hello", MatchMode::Literal, false)
+        );
+        assert_eq!(
+            vec![dir.join("README")],
+            narrow(r"a\s+frog", MatchMode::Regex, true)
+        );
+        assert_eq!(4, narrow("fn", MatchMode::Literal, true).len());
+        // UTF-16 文件按解码之后的内容进索引
+        assert_eq!(
+            vec![dir.join("u16.txt")],
+            narrow("tadpole", MatchMode::Literal, true)
+        );
+
+        // 改过的文件在重建索引前也会被搜索, 重建时只读它一个
+        fs::write(dir.join("README"), "frobnicate the frog, a bigger file\n").unwrap();
+        fs::remove_file(dir.join("src/main.rs")).unwrap();
+        assert_eq!(2, narrow("frob", MatchMode::Literal, true).len());
+        let stats = build_index(&dir, &mut Vec::new()).unwrap();
+        assert_eq!((3, 1, 1), (stats.files, stats.updated, stats.removed));
+        assert_eq!(2, narrow("frob", MatchMode::Literal, true).len());
+        assert!(narrow("zzz", MatchMode::Literal, true).is_empty());
+
+        // 命令行上直接给出的文件即使在索引的目录下也照常搜索
+        let readme = dir.join("README");
+        let narrowed = super::narrow(
+            &[dir.clone(), readme.clone()],
+            walked(&dir),
+            &["zzz".to_string()],
+            MatchMode::Literal,
+            true,
+        )
+        .unwrap();
+        assert_eq!(vec![readme], narrowed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
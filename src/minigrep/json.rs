@@ -0,0 +1,108 @@
+// --json 输出用到的最小 JSON 编码, 只需要写不需要解析, 所以没有引入 serde
+use std::io::{self, Write};
+use std::path::Path;
+
+/// 写一个 JSON 字符串, 包括两边的引号
+pub fn write_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut last = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{08}' => "\\b",
+            '\u{0c}' => "\\f",
+            c if (c as u32) < 0x20 => {
+                out.write_all(&s.as_bytes()[last..i])?;
+                write!(out, "\\u{:04x}", c as u32)?;
+                last = i + c.len_utf8();
+                continue;
+            }
+            _ => continue,
+        };
+        out.write_all(&s.as_bytes()[last..i])?;
+        out.write_all(escaped.as_bytes())?;
+        last = i + c.len_utf8();
+    }
+    out.write_all(&s.as_bytes()[last..])?;
+    out.write_all(b"\"")
+}
+
+/// 任意字节: 合法 UTF-8 写成 {"text":"..."}, 否则写成 {"bytes":"<base64>"}
+///
+/// JSON 字符串只能表示 Unicode, 直接替换掉非法字节会让使用者拿不到原始数据
+pub fn write_data<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            out.write_all(b"{\"text\":")?;
+            write_string(out, text)?;
+        }
+        Err(_) => {
+            out.write_all(b"{\"bytes\":\"")?;
+            out.write_all(base64(bytes).as_bytes())?;
+            out.write_all(b"\"")?;
+        }
+    }
+    out.write_all(b"}")
+}
+
+/// 路径在 unix 上可以不是 UTF-8, 这时按原始字节输出
+pub fn write_path<W: Write>(out: &mut W, path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        write_data(out, path.as_os_str().as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        write_data(out, path.to_string_lossy().as_bytes())
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(bytes: &[u8]) -> String {
+        let mut out = Vec::new();
+        write_data(&mut out, bytes).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(
+            r#"{"text":"a\"b\\c\n\u0001é"}"#,
+            data("a\"b\\c\n\u{1}é".as_bytes())
+        );
+    }
+
+    #[test]
+    fn non_utf8_as_base64() {
+        assert_eq!(r#"{"bytes":"/w=="}"#, data(b"\xff"));
+        assert_eq!(r#"{"bytes":"YWL/"}"#, data(b"ab\xff"));
+        assert_eq!(r#"{"bytes":"YWJj/2Q="}"#, data(b"abc\xffd"));
+    }
+}
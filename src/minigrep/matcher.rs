@@ -0,0 +1,540 @@
+use crate::minigrep::aho_corasick::AhoCorasick;
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use std::iter;
+use std::ops::Range;
+
+/// 查询字符串的匹配方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// 按字面量子串匹配
+    #[default]
+    Literal,
+    /// 把查询字符串当作正则表达式
+    Regex,
+}
+
+/// 大小写是否敏感
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseMode {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// 模式里有大写字母时才区分大小写
+    Smart,
+}
+
+impl CaseMode {
+    /// 多个模式时只要有一个带大写字母, Smart 就区分大小写
+    pub fn is_sensitive<S: AsRef<str>>(self, patterns: &[S]) -> bool {
+        match self {
+            CaseMode::Sensitive => true,
+            CaseMode::Insensitive => false,
+            CaseMode::Smart => patterns
+                .iter()
+                .any(|p| p.as_ref().chars().any(char::is_uppercase)),
+        }
+    }
+}
+
+/// 一行中的一处匹配: 字节范围, 以及是第几个模式匹配上的
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submatch {
+    pub range: Range<usize>,
+    pub pattern: usize,
+}
+
+/// 编译好的查询, 在 run 中只构建一次, 之后每一行都复用
+pub struct Matcher {
+    kind: Kind,
+    // -w: 匹配的前后都不能是单词字符
+    whole_word: bool,
+}
+
+enum Kind {
+    Literal(String),
+    // 大小写不敏感的字面量, 预先折叠好的查询字符
+    Folded(Vec<char>),
+    // 多个字面量模式, 用自动机一遍扫描
+    Literals(AhoCorasick),
+    Regex(Regex),
+    // 多个正则各自编译, 捕获组的名字可以重复; set 先挑出这一行能匹配的模式,
+    // 再用这些模式自己的 Regex 找位置和捕获组
+    Regexes { set: RegexSet, regexes: Vec<Regex> },
+}
+
+impl Matcher {
+    /// 正则写错时返回 regex::Error 而不是 panic
+    pub fn new(
+        query: &str,
+        mode: MatchMode,
+        case_sensitive: bool,
+    ) -> Result<Matcher, regex::Error> {
+        Matcher::with_patterns(&[query], mode, case_sensitive)
+    }
+
+    /// 多个模式 (-e 重复或 -f 文件), 任意一个匹配上这一行就算匹配
+    pub fn with_patterns<S: AsRef<str>>(
+        patterns: &[S],
+        mode: MatchMode,
+        case_sensitive: bool,
+    ) -> Result<Matcher, regex::Error> {
+        let kind = match (mode, patterns) {
+            (_, []) | (MatchMode::Literal, [_, _, ..]) => {
+                Kind::Literals(AhoCorasick::new(patterns, !case_sensitive))
+            }
+            (MatchMode::Literal, [query]) => {
+                return Ok(Matcher::literal(query.as_ref(), case_sensitive))
+            }
+            // 大小写不敏感交给正则的 (?i) 标志处理, regex 本身就按 Unicode 简单折叠比较
+            (MatchMode::Regex, [query]) => Kind::Regex(
+                RegexBuilder::new(query.as_ref())
+                    .case_insensitive(!case_sensitive)
+                    .build()?,
+            ),
+            (MatchMode::Regex, _) => {
+                // 先单独编译每个模式, 报错时指向具体的模式
+                let regexes = patterns
+                    .iter()
+                    .map(|p| {
+                        RegexBuilder::new(p.as_ref())
+                            .case_insensitive(!case_sensitive)
+                            .build()
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let set = RegexSetBuilder::new(patterns)
+                    .case_insensitive(!case_sensitive)
+                    .build()?;
+                Kind::Regexes { set, regexes }
+            }
+        };
+        Ok(Matcher {
+            kind,
+            whole_word: false,
+        })
+    }
+
+    /// 字面量查询不会出错
+    pub fn literal(query: &str, case_sensitive: bool) -> Matcher {
+        let kind = if case_sensitive {
+            Kind::Literal(query.to_string())
+        } else {
+            Kind::Folded(query.chars().map(fold).collect())
+        };
+        Matcher {
+            kind,
+            whole_word: false,
+        }
+    }
+
+    /// 只接受前后都是单词边界的匹配
+    pub fn whole_word(mut self, yes: bool) -> Matcher {
+        self.whole_word = yes;
+        self
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match &self.kind {
+            Kind::Literal(query) if !self.whole_word => line.contains(query.as_str()),
+            Kind::Regex(re) if !self.whole_word => re.is_match(line),
+            Kind::Regexes { set, .. } if !self.whole_word => set.is_match(line),
+            _ => self.find_at(line, 0).is_some(),
+        }
+    }
+
+    /// 从 start 开始找下一个匹配, 返回的是在 line 中的字节范围和匹配上的模式
+    pub fn find_at(&self, line: &str, start: usize) -> Option<Submatch> {
+        let mut start = start;
+        loop {
+            let found = match &self.kind {
+                Kind::Literal(query) => line[start..].find(query.as_str()).map(|i| Submatch {
+                    range: start + i..start + i + query.len(),
+                    pattern: 0,
+                }),
+                Kind::Folded(query) => {
+                    find_folded(line, start, query).map(|range| Submatch { range, pattern: 0 })
+                }
+                // 自动机自己处理单词边界, 被拒绝的候选不会挡住同一位置更短的匹配
+                Kind::Literals(ac) => {
+                    return ac.find_at(line, start, |range| {
+                        !self.whole_word || is_whole_word(line, range)
+                    })
+                }
+                Kind::Regex(re) => re.find_at(line, start).map(|m| Submatch {
+                    range: m.range(),
+                    pattern: 0,
+                }),
+                // 和把模式用 | 连起来一样: 起点最靠前的匹配, 起点相同时取排在前面的模式
+                Kind::Regexes { set, regexes } => set
+                    .matches_at(line, start)
+                    .iter()
+                    .filter_map(|i| regexes[i].find_at(line, start).map(|m| (m.range(), i)))
+                    .min_by_key(|(range, i)| (range.start, *i))
+                    .map(|(range, pattern)| Submatch { range, pattern }),
+            }?;
+            if !self.whole_word || is_whole_word(line, &found.range) {
+                return Some(found);
+            }
+            // 不是完整的单词, 从下一个字符开始接着找
+            start = found.range.start + line[found.range.start..].chars().next()?.len_utf8();
+        }
+    }
+
+    /// 一行里所有不重叠的匹配, 用来高亮
+    pub fn find_iter(&self, line: &str) -> Vec<Submatch> {
+        let mut start = 0;
+        iter::from_fn(|| self.next_match(line, &mut start)).collect()
+    }
+
+    /// 从 *start 开始的下一个非空匹配, 同时把 *start 移到它后面, 用来一个一个地取匹配
+    pub fn next_match(&self, line: &str, start: &mut usize) -> Option<Submatch> {
+        while *start <= line.len() {
+            let m = self.find_at(line, *start)?;
+            let span = &m.range;
+            // 空匹配时至少前进一个字符, 否则会死循环
+            *start = if span.is_empty() {
+                match line[span.end..].chars().next() {
+                    Some(c) => span.end + c.len_utf8(),
+                    None => line.len() + 1,
+                }
+            } else {
+                span.end
+            };
+            if !span.is_empty() {
+                return Some(m);
+            }
+        }
+        None
+    }
+
+    /// 一行里所有不重叠的匹配, 包括空匹配, 用来替换: `^` 和 `$` 也要换掉
+    ///
+    /// 和 regex 的 replace_all 一样, 空匹配之后前进一个字符, 紧跟在上一个匹配结尾的空匹配不算
+    pub fn find_all(&self, line: &str) -> Vec<Submatch> {
+        let mut found = Vec::new();
+        let mut start = 0;
+        let mut last_end = None;
+        while start <= line.len() {
+            let m = match self.find_at(line, start) {
+                Some(m) => m,
+                None => break,
+            };
+            if m.range.is_empty() {
+                start = match line[m.range.end..].chars().next() {
+                    Some(c) => m.range.end + c.len_utf8(),
+                    None => line.len() + 1,
+                };
+                if last_end == Some(m.range.end) {
+                    continue;
+                }
+            } else {
+                start = m.range.end;
+            }
+            last_end = Some(m.range.end);
+            found.push(m);
+        }
+        found
+    }
+
+    pub fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        contents
+            .lines()
+            .filter(|line| self.is_match(line))
+            .collect()
+    }
+
+    /// 把 line 中的 matches (find_all 的结果) 换成 template, 返回新的行和替换后文本的位置
+    ///
+    /// template 里 `$1` `${name}` 引用正则的捕获组, `$0` 是整个匹配, `$$` 是 `$` 本身;
+    /// 多个模式时编号和名字都是匹配上的那个模式自己的
+    pub fn replace(
+        &self,
+        line: &str,
+        matches: &[Submatch],
+        template: &str,
+    ) -> (String, Vec<Submatch>) {
+        let mut replaced = String::with_capacity(line.len());
+        let mut spans = Vec::with_capacity(matches.len());
+        let mut last = 0;
+        for m in matches {
+            replaced.push_str(&line[last..m.range.start]);
+            let start = replaced.len();
+            self.expand(line, m, template, &mut replaced);
+            spans.push(Submatch {
+                range: start..replaced.len(),
+                pattern: m.pattern,
+            });
+            last = m.range.end;
+        }
+        replaced.push_str(&line[last..]);
+        (replaced, spans)
+    }
+
+    fn expand(&self, line: &str, m: &Submatch, template: &str, out: &mut String) {
+        // 从匹配的起点重新跑一次, 拿到捕获组
+        let caps = match &self.kind {
+            Kind::Regex(re) => re.captures_at(line, m.range.start),
+            Kind::Regexes { regexes, .. } => regexes[m.pattern].captures_at(line, m.range.start),
+            _ => None,
+        };
+        let group = |name: &str| -> Option<&str> {
+            let caps = match &caps {
+                Some(caps) => caps,
+                None => return (name == "0").then(|| &line[m.range.clone()]),
+            };
+            match name.parse::<usize>() {
+                Ok(index) => caps.get(index).map(|g| g.as_str()),
+                Err(_) => caps.name(name).map(|g| g.as_str()),
+            }
+        };
+
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            out.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                out.push('$');
+                rest = after;
+                continue;
+            }
+            // ${name} 可以紧跟着其他字母, $name 取尽量长的名字, 和 regex 的 expand 一致
+            let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => ("", rest),
+                }
+            } else {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+            if name.is_empty() {
+                out.push('$');
+                continue;
+            }
+            // 不存在的组替换成空串
+            out.push_str(group(name).unwrap_or(""));
+            rest = after;
+        }
+        out.push_str(rest);
+    }
+}
+
+/// Unicode 简单大小写折叠 (CaseFolding.txt 中的 C 和 S 映射), 一个字符只映射到一个字符
+///
+/// 大部分字符和 to_lowercase 的结果一致, 但 to_lowercase 会把 'İ' 变成两个字符,
+/// 而且不会把 'ς' 'ſ' 这类变体和它们的标准形式放在一起, 这里单独处理
+pub fn fold(c: char) -> char {
+    match c {
+        'ς' => 'σ',
+        'ϐ' => 'β',
+        'ϑ' => 'θ',
+        'ϕ' => 'φ',
+        'ϖ' => 'π',
+        'ϰ' => 'κ',
+        'ϱ' => 'ρ',
+        'ϵ' => 'ε',
+        'ſ' => 's',
+        'ẛ' => 'ṡ',
+        '\u{1fbe}' => 'ι',
+        _ => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(l), None) => l,
+                // 简单折叠里没有一对多的映射, 比如 'İ' 只和它自己相等
+                _ => c,
+            }
+        }
+    }
+}
+
+// 逐个字符折叠后比较, 不需要为每一行分配新的字符串
+fn find_folded(line: &str, start: usize, query: &[char]) -> Option<Range<usize>> {
+    if query.is_empty() {
+        return Some(start..start);
+    }
+    'outer: for (i, _) in line[start..].char_indices() {
+        let begin = start + i;
+        let mut end = begin;
+        let mut chars = line[begin..].chars();
+        for &q in query {
+            match chars.next() {
+                Some(c) if fold(c) == q => end += c.len_utf8(),
+                _ => continue 'outer,
+            }
+        }
+        return Some(begin..end);
+    }
+    None
+}
+
+// 单词字符: 字母, 数字和下划线, 都按 Unicode 判断
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_whole_word(line: &str, span: &Range<usize>) -> bool {
+    let before = line[..span.start].chars().next_back();
+    let after = line[span.end..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(matcher: &Matcher, line: &str) -> Vec<Range<usize>> {
+        matcher
+            .find_iter(line)
+            .into_iter()
+            .map(|m| m.range)
+            .collect()
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let matcher = Matcher::new(r"^r\w+:$", MatchMode::Regex, false).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+trust me.";
+        assert_eq!(vec!["Rust:"], matcher.search(contents));
+    }
+
+    #[test]
+    fn match_spans() {
+        let matcher = Matcher::new("ab", MatchMode::Literal, true).unwrap();
+        assert_eq!(vec![0..2, 4..6], spans(&matcher, "abxxab"));
+
+        let matcher = Matcher::new("RUST", MatchMode::Literal, false).unwrap();
+        assert_eq!(vec![4..8], spans(&matcher, "Új Rust"));
+
+        let matcher = Matcher::new("x*", MatchMode::Regex, true).unwrap();
+        assert_eq!(vec![1..3], spans(&matcher, "axxb"));
+    }
+
+    #[test]
+    fn unicode_case_folding() {
+        let matcher = Matcher::literal("STRAẞE", false);
+        assert_eq!(vec![0..7], spans(&matcher, "straße"));
+        // 简单折叠里 'İ' 不等于 'i', 'ı' 也不等于 'I'
+        assert!(!Matcher::literal("i", false).is_match("İstanbul"));
+        assert!(!Matcher::literal("I", false).is_match("kırk"));
+        assert!(Matcher::literal("ΟΔΟΣ", false).is_match("οδος"));
+        assert!(Matcher::literal("οδοσ", false).is_match("ΟΔΟΣ οδος"));
+        assert!(Matcher::literal("οδος", false).is_match("ΟΔΟΣ"));
+        // 开尔文符号 K 折叠成普通的 k
+        assert_eq!(
+            vec![1..4],
+            spans(&Matcher::literal("k", false), "5\u{212a}")
+        );
+    }
+
+    #[test]
+    fn whole_word() {
+        let matcher = Matcher::literal("rust", false).whole_word(true);
+        assert_eq!(vec![16..20], spans(&matcher, "trust me, Rust_ rust"));
+        assert!(!matcher.is_match("rustacean"));
+        assert!(!matcher.is_match("rustété"));
+
+        let matcher = Matcher::new(r"fo+", MatchMode::Regex, true)
+            .unwrap()
+            .whole_word(true);
+        assert_eq!(vec![5..8], spans(&matcher, "foox foo"));
+    }
+
+    #[test]
+    fn many_patterns() {
+        let matcher = Matcher::with_patterns(
+            &["unwrap", "expect", "Unwrap_or"],
+            MatchMode::Literal,
+            false,
+        )
+        .unwrap();
+        let found = matcher.find_iter("x.unwrap_or(1).expect(\"y\")");
+        assert_eq!(
+            vec![
+                Submatch {
+                    range: 2..11,
+                    pattern: 2
+                },
+                Submatch {
+                    range: 15..21,
+                    pattern: 1
+                }
+            ],
+            found
+        );
+
+        let matcher = Matcher::with_patterns(&["fo+", "(b)(a)r", "ba"], MatchMode::Regex, true)
+            .unwrap()
+            .whole_word(true);
+        let found = matcher.find_iter("bar foo bax ba");
+        let patterns: Vec<usize> = found.iter().map(|m| m.pattern).collect();
+        assert_eq!(vec![1, 0, 2], patterns);
+
+        // 没有模式 (比如 -f 一个空文件) 时什么都不匹配
+        let none: [&str; 0] = [];
+        assert!(!Matcher::with_patterns(&none, MatchMode::Regex, true)
+            .unwrap()
+            .is_match("anything"));
+    }
+
+    #[test]
+    fn replace_with_captures() {
+        let matcher = Matcher::new(r"(\w+)@(?P<host>\w+)", MatchMode::Regex, true).unwrap();
+        let line = "mail ann@home, bob@work";
+        let matches = matcher.find_iter(line);
+        let (replaced, spans) = matcher.replace(line, &matches, "${host}:$1 $$$9");
+        assert_eq!("mail home:ann $, work:bob $", replaced);
+        assert_eq!(
+            vec![5..15, 17..27],
+            spans.into_iter().map(|m| m.range).collect::<Vec<_>>()
+        );
+
+        // 多个模式时 $1 指的是匹配上的那个模式自己的第一个组
+        let matcher =
+            Matcher::with_patterns(&[r"(a)(b)", r"x(y)"], MatchMode::Regex, true).unwrap();
+        let matches = matcher.find_iter("ab xy");
+        assert_eq!("[a] [y]", matcher.replace("ab xy", &matches, "[$1]").0);
+
+        // 不同的模式可以用同一个组名
+        let matcher =
+            Matcher::with_patterns(&[r"(?P<k>\w)=", r"(?P<k>\w):"], MatchMode::Regex, true)
+                .unwrap();
+        let matches = matcher.find_iter("a=1 b:2");
+        assert_eq!(
+            vec![0, 1],
+            matches.iter().map(|m| m.pattern).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "<a>1 <b>2",
+            matcher.replace("a=1 b:2", &matches, "<${k}>").0
+        );
+
+        let matcher = Matcher::literal("rust", false);
+        let matches = matcher.find_iter("Rust!");
+        assert_eq!("<Rust>!", matcher.replace("Rust!", &matches, "<$0>").0);
+    }
+
+    #[test]
+    fn replace_empty_matches() {
+        let replace_all = |patterns: &[&str], line: &str, template: &str| {
+            let matcher = Matcher::with_patterns(patterns, MatchMode::Regex, true).unwrap();
+            let matches = matcher.find_all(line);
+            matcher.replace(line, &matches, template).0
+        };
+        assert_eq!("// a", replace_all(&["^"], "a", "// "));
+        assert_eq!("a;", replace_all(&["$"], "a", ";"));
+        assert_eq!("XoX", replace_all(&["fo", "$"], "foo", "X"));
+        // 和 regex 的 replace_all 一样, 紧跟在匹配后面的空匹配不算
+        assert_eq!("XaXbX", replace_all(&["x*"], "axxb", "X"));
+        assert_eq!("X", replace_all(&["a*"], "aaa", "X"));
+    }
+
+    #[test]
+    fn invalid_regex_is_error() {
+        assert!(Matcher::with_patterns(&["ok", "(unclosed"], MatchMode::Regex, true).is_err());
+        assert!(Matcher::new("(unclosed", MatchMode::Regex, true).is_err());
+    }
+}
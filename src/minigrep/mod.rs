@@ -0,0 +1,18 @@
+// minigrep 的各个组成部分, lib.rs 中的 Config / run 负责把它们串起来
+pub mod aho_corasick;
+pub mod args;
+pub mod encoding;
+pub mod error;
+pub mod fuzzy;
+pub mod ignore;
+pub mod index;
+pub mod json;
+pub mod matcher;
+pub mod output;
+pub mod rc;
+pub mod replace;
+pub mod rust_item;
+pub mod search;
+pub mod searcher;
+pub mod tar;
+pub mod walk;
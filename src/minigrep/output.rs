@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::minigrep::json;
+use crate::minigrep::matcher::Submatch;
+
+/// -A / -B / -C 指定的上下文行数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl Context {
+    fn is_enabled(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+}
+
+/// --color 的取值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    /// 只有 stdout 是终端并且没有设置 NO_COLOR 时才上色
+    Auto,
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Option<ColorChoice> {
+        match s {
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            "auto" => Some(ColorChoice::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn should_color(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org/ 约定: 存在且不为空就表示不要颜色
+            ColorChoice::Auto => {
+                io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        }
+    }
+}
+
+// 和 GNU grep 默认的配色一致
+const COLOR_PATH: &str = "\x1b[35m";
+const COLOR_NUMBER: &str = "\x1b[32m";
+const COLOR_SEPARATOR: &str = "\x1b[36m";
+const COLOR_MATCH: &str = "\x1b[1;31m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// 输出什么: 匹配的行, 还是只输出每个文件的汇总
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    #[default]
+    Lines,
+    /// -c 每个文件匹配的行数
+    Count,
+    /// -l 有匹配的文件名
+    FilesWithMatches,
+    /// -L 没有匹配的文件名
+    FilesWithoutMatch,
+    /// -q 什么都不输出, 只看退出码
+    Quiet,
+}
+
+/// 每一行前面要带哪些信息, 以及是否上色
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrintOptions {
+    pub mode: OutputMode,
+    pub context: Context,
+    pub with_filename: bool,
+    pub line_number: bool,
+    /// 第一个匹配所在的列, 从 1 开始, 按字节计
+    pub column: bool,
+    /// 行首在文件中的字节偏移, 从 0 开始
+    pub byte_offset: bool,
+    /// 在匹配行前面列出匹配上的模式, 有多个模式时用来区分
+    pub show_pattern: bool,
+    pub color: bool,
+    /// 每个事件输出一行 JSON, 给程序读的格式
+    pub json: bool,
+}
+
+/// 搜索的统计数字, 单个文件和全部文件的汇总都用它
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub matched_lines: usize,
+    pub matches: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+/// 逐行接收 (行号, 内容, 匹配位置), 负责上下文和分隔符的输出
+///
+/// 前文只缓存最近的 before 行, 后文用计数器倒数, 所以不需要整个文件都在内存里.
+/// 相邻或重叠的窗口会自然合并, 只有不连续的两段之间才输出 `--`
+pub struct Printer<W: Write> {
+    out: W,
+    options: PrintOptions,
+    // 模式原文, show_pattern 时按 Submatch::pattern 取出来输出
+    patterns: Arc<Vec<String>>,
+    path: PathBuf,
+    // JSON 模式下只有真的输出了内容的文件才有 begin / end 事件
+    begun: bool,
+    // 当前文件是二进制文件, 匹配的行不输出, 只报告有匹配
+    binary: bool,
+    binary_reported: bool,
+    file_stats: Stats,
+    total: Stats,
+    // (行号, 字节偏移, 内容)
+    before: VecDeque<(usize, u64, Vec<u8>)>,
+    after_left: usize,
+    // 当前文件中最后输出的行号
+    last_printed: Option<usize>,
+    // 之前的文件是否输出过内容, 决定新文件的第一段前是否要 `--`
+    printed_any: bool,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W, options: PrintOptions) -> Printer<W> {
+        Printer {
+            out,
+            options,
+            patterns: Arc::default(),
+            path: PathBuf::new(),
+            begun: false,
+            binary: false,
+            binary_reported: false,
+            file_stats: Stats::default(),
+            total: Stats::default(),
+            before: VecDeque::with_capacity(options.context.before),
+            after_left: 0,
+            last_printed: None,
+            printed_any: false,
+        }
+    }
+
+    /// 设置模式原文, 多个线程的 Printer 共用同一份
+    pub fn set_patterns(&mut self, patterns: Arc<Vec<String>>) {
+        self.patterns = patterns;
+    }
+
+    /// 开始一个新文件, 清掉上一个文件留下的上下文状态
+    pub fn begin_file(&mut self, path: &Path) {
+        self.path.clear();
+        self.path.push(path);
+        self.begun = false;
+        self.binary = false;
+        self.binary_reported = false;
+        self.file_stats = Stats {
+            searches: 1,
+            ..Stats::default()
+        };
+        self.before.clear();
+        self.after_left = 0;
+        if self.last_printed.take().is_some() {
+            self.printed_any = true;
+        }
+    }
+
+    /// 当前文件搜索完毕, 返回这个文件的统计
+    pub fn end_file(&mut self) -> io::Result<Stats> {
+        let stats = self.file_stats;
+        self.total.add(stats);
+        match self.options.mode {
+            OutputMode::Count => {
+                if self.options.with_filename {
+                    self.write_path(":")?;
+                }
+                writeln!(self.out, "{}", stats.matched_lines)?;
+            }
+            OutputMode::FilesWithMatches if stats.matched_lines > 0 => {
+                self.write_path("")?;
+                writeln!(self.out)?;
+            }
+            OutputMode::FilesWithoutMatch if stats.matched_lines == 0 => {
+                self.write_path("")?;
+                writeln!(self.out)?;
+            }
+            _ => {}
+        }
+        if self.begun {
+            self.out
+                .write_all(b"{\"type\":\"end\",\"data\":{\"path\":")?;
+            json::write_path(&mut self.out, &self.path)?;
+            self.out.write_all(b",\"stats\":")?;
+            write_stats(&mut self.out, stats)?;
+            self.out.write_all(b"}}\n")?;
+        }
+        Ok(stats)
+    }
+
+    /// 所有文件都搜完了, JSON 模式下输出汇总
+    pub fn finish(&mut self) -> io::Result<Stats> {
+        if self.options.json {
+            self.out
+                .write_all(b"{\"type\":\"summary\",\"data\":{\"stats\":")?;
+            write_stats(&mut self.out, self.total)?;
+            self.out.write_all(b"}}\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.total)
+    }
+
+    /// 从现在起把当前文件当作二进制文件, 已经输出的行不受影响
+    pub fn set_binary(&mut self) {
+        self.binary = true;
+        self.before.clear();
+        self.after_left = 0;
+    }
+
+    /// 当前文件不需要再往下读了: 二进制文件已经报告过有匹配
+    pub fn is_done(&self) -> bool {
+        self.binary_reported
+    }
+
+    /// 还在输出匹配行后面的上下文
+    pub fn in_after_context(&self) -> bool {
+        self.after_left > 0
+    }
+
+    /// line_number 从 1 开始, is_match 表示这一行被选中 (-v 时是没有匹配的行),
+    /// matches 是要高亮的位置
+    ///
+    /// line 是原始字节, matches 也是按原始字节算的. 文本输出时不是 UTF-8 的部分
+    /// 显示成 U+FFFD, JSON 输出里整行按 {"bytes":...} 原样给出
+    pub fn line(
+        &mut self,
+        line_number: usize,
+        byte_offset: u64,
+        line: &[u8],
+        is_match: bool,
+        matches: &[Submatch],
+    ) -> io::Result<()> {
+        if is_match {
+            if self.file_stats.matched_lines == 0 {
+                self.file_stats.searches_with_match = 1;
+            }
+            self.file_stats.matched_lines += 1;
+            self.file_stats.matches += matches.len();
+        }
+        // 其他模式只需要统计
+        if self.options.mode != OutputMode::Lines {
+            return Ok(());
+        }
+        // 和 grep 一样二进制文件只说一句有匹配, JSON 里只体现在统计数字上
+        if self.binary {
+            if is_match && !self.binary_reported {
+                self.binary_reported = true;
+                if !self.options.json {
+                    writeln!(self.out, "Binary file {} matches", self.path.display())?;
+                    self.printed_any = true;
+                }
+            }
+            return Ok(());
+        }
+
+        let context = self.options.context;
+        if is_match {
+            while let Some((n, offset, before)) = self.before.pop_front() {
+                self.write_line(n, offset, &before, false, &[])?;
+            }
+            self.write_line(line_number, byte_offset, line, true, matches)?;
+            self.after_left = context.after;
+        } else if self.after_left > 0 {
+            self.after_left -= 1;
+            self.write_line(line_number, byte_offset, line, false, &[])?;
+        } else if context.before > 0 {
+            // 缓存满了就复用最旧那一行的 Vec, 避免每行都分配
+            let mut buf = if self.before.len() == context.before {
+                self.before
+                    .pop_front()
+                    .map(|(_, _, s)| s)
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            buf.clear();
+            buf.extend_from_slice(line);
+            self.before.push_back((line_number, byte_offset, buf));
+        }
+        Ok(())
+    }
+
+    /// --rust-item 找到的一个条目, lines 是首尾两行 (从 1 开始)
+    ///
+    /// 和匹配行一样计入统计, 输出成 `path:start-end:description`, 总是带着文件名
+    pub fn item(&mut self, lines: (usize, usize), description: &str) -> io::Result<()> {
+        if self.file_stats.matched_lines == 0 {
+            self.file_stats.searches_with_match = 1;
+        }
+        self.file_stats.matched_lines += 1;
+        self.file_stats.matches += 1;
+        if self.options.mode != OutputMode::Lines {
+            return Ok(());
+        }
+        self.last_printed = Some(lines.1);
+        if self.options.json {
+            self.write_json_begin()?;
+            self.out
+                .write_all(b"{\"type\":\"item\",\"data\":{\"path\":")?;
+            json::write_path(&mut self.out, &self.path)?;
+            write!(
+                self.out,
+                ",\"line_start\":{},\"line_end\":{},\"item\":",
+                lines.0, lines.1
+            )?;
+            json::write_string(&mut self.out, description)?;
+            return self.out.write_all(b"}}\n");
+        }
+        self.write_path(":")?;
+        self.write_colored(COLOR_NUMBER, &format!("{}-{}", lines.0, lines.1))?;
+        self.write_colored(COLOR_SEPARATOR, ":")?;
+        writeln!(self.out, "{}", description)
+    }
+
+    fn write_line(
+        &mut self,
+        line_number: usize,
+        byte_offset: u64,
+        line: &[u8],
+        is_match: bool,
+        matches: &[Submatch],
+    ) -> io::Result<()> {
+        let options = self.options;
+        if options.json {
+            return self.write_json_line(line_number, byte_offset, line, is_match, matches);
+        }
+        if options.context.is_enabled() {
+            let gap = match self.last_printed {
+                Some(last) => line_number > last + 1,
+                None => self.printed_any,
+            };
+            if gap {
+                self.write_colored(COLOR_SEPARATOR, "--")?;
+                writeln!(self.out)?;
+            }
+        }
+        self.last_printed = Some(line_number);
+
+        // grep 的习惯: 匹配行用 `:` 分隔, 上下文行用 `-`
+        let sep = if is_match { ":" } else { "-" };
+        if options.with_filename {
+            self.write_path(sep)?;
+        }
+        if options.line_number {
+            self.write_colored(COLOR_NUMBER, &line_number.to_string())?;
+            self.write_colored(COLOR_SEPARATOR, sep)?;
+        }
+        if options.column {
+            if let Some(first) = matches.first() {
+                self.write_colored(COLOR_NUMBER, &(first.range.start + 1).to_string())?;
+                self.write_colored(COLOR_SEPARATOR, sep)?;
+            }
+        }
+        if options.byte_offset {
+            self.write_colored(COLOR_NUMBER, &byte_offset.to_string())?;
+            self.write_colored(COLOR_SEPARATOR, sep)?;
+        }
+        if options.show_pattern && !matches.is_empty() {
+            // 同一个模式在一行里匹配多次只列一次, 按第一次出现的顺序
+            let mut seen: Vec<usize> = Vec::new();
+            for m in matches {
+                if !seen.contains(&m.pattern) {
+                    seen.push(m.pattern);
+                }
+            }
+            let patterns = Arc::clone(&self.patterns);
+            let names: Vec<&str> = seen
+                .iter()
+                .map(|&i| patterns.get(i).map_or("", String::as_str))
+                .collect();
+            self.write_colored(COLOR_MATCH, &names.join(","))?;
+            self.write_colored(COLOR_SEPARATOR, sep)?;
+        }
+
+        // 匹配的边界不会切开一段非法字节, 分段解码和整行解码的结果一样
+        if options.color {
+            let mut last = 0;
+            for m in matches {
+                let before = String::from_utf8_lossy(&line[last..m.range.start]);
+                self.out.write_all(before.as_bytes())?;
+                self.write_colored(
+                    COLOR_MATCH,
+                    &String::from_utf8_lossy(&line[m.range.clone()]),
+                )?;
+                last = m.range.end;
+            }
+            self.out
+                .write_all(String::from_utf8_lossy(&line[last..]).as_bytes())?;
+        } else {
+            self.out
+                .write_all(String::from_utf8_lossy(line).as_bytes())?;
+        }
+        writeln!(self.out)
+    }
+
+    // 和 ripgrep --json 的格式一致: 匹配行是 match 事件, 上下文行是 context 事件
+    fn write_json_line(
+        &mut self,
+        line_number: usize,
+        byte_offset: u64,
+        line: &[u8],
+        is_match: bool,
+        matches: &[Submatch],
+    ) -> io::Result<()> {
+        self.write_json_begin()?;
+        let kind = if is_match { "match" } else { "context" };
+        write!(self.out, "{{\"type\":\"{}\",\"data\":{{\"path\":", kind)?;
+        json::write_path(&mut self.out, &self.path)?;
+        self.out.write_all(b",\"lines\":")?;
+        json::write_data(&mut self.out, line)?;
+        write!(
+            self.out,
+            ",\"line_number\":{},\"absolute_offset\":{},\"submatches\":[",
+            line_number, byte_offset
+        )?;
+        for (i, m) in matches.iter().enumerate() {
+            if i > 0 {
+                self.out.write_all(b",")?;
+            }
+            self.out.write_all(b"{\"match\":")?;
+            json::write_data(&mut self.out, &line[m.range.clone()])?;
+            write!(
+                self.out,
+                ",\"start\":{},\"end\":{},\"pattern\":{}}}",
+                m.range.start, m.range.end, m.pattern
+            )?;
+        }
+        self.out.write_all(b"]}}\n")
+    }
+
+    // 每个文件第一次输出内容之前的 begin 事件
+    fn write_json_begin(&mut self) -> io::Result<()> {
+        if self.begun {
+            return Ok(());
+        }
+        self.begun = true;
+        self.out
+            .write_all(b"{\"type\":\"begin\",\"data\":{\"path\":")?;
+        json::write_path(&mut self.out, &self.path)?;
+        self.out.write_all(b"}}\n")
+    }
+
+    // 文件名加上后面的分隔符
+    fn write_path(&mut self, sep: &str) -> io::Result<()> {
+        let path = self.path.display().to_string();
+        self.write_colored(COLOR_PATH, &path)?;
+        if sep.is_empty() {
+            return Ok(());
+        }
+        self.write_colored(COLOR_SEPARATOR, sep)
+    }
+
+    fn write_colored(&mut self, color: &str, text: &str) -> io::Result<()> {
+        if self.options.color {
+            write!(self.out, "{}{}{}", color, text, COLOR_RESET)
+        } else {
+            self.out.write_all(text.as_bytes())
+        }
+    }
+
+    /// 写入另一个 Printer 在别的线程里为单个文件生成好的输出
+    ///
+    /// 那个 Printer 不知道前面的文件有没有输出, 所以文件之间的 `--` 在这里补上
+    pub fn file_output(&mut self, output: &[u8], stats: Stats) -> io::Result<()> {
+        self.total.add(stats);
+        if output.is_empty() {
+            return Ok(());
+        }
+        if !self.options.json && self.options.context.is_enabled() && self.printed_any {
+            self.write_colored(COLOR_SEPARATOR, "--")?;
+            writeln!(self.out)?;
+        }
+        self.printed_any = true;
+        self.out.write_all(output)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn write_stats<W: Write>(out: &mut W, stats: Stats) -> io::Result<()> {
+    write!(
+        out,
+        "{{\"searches\":{},\"searches_with_match\":{},\"matched_lines\":{},\"matches\":{}}}",
+        stats.searches, stats.searches_with_match, stats.matched_lines, stats.matches
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print(contents: &str, query: &str, options: PrintOptions) -> String {
+        let mut printer = Printer::new(Vec::new(), options);
+        printer.begin_file(Path::new("a.txt"));
+        let mut offset = 0;
+        for (i, line) in contents.lines().enumerate() {
+            let matches: Vec<_> = line
+                .match_indices(query)
+                .map(|(start, m)| Submatch {
+                    range: start..start + m.len(),
+                    pattern: 0,
+                })
+                .collect();
+            printer
+                .line(
+                    i + 1,
+                    offset,
+                    line.as_bytes(),
+                    !matches.is_empty(),
+                    &matches,
+                )
+                .unwrap();
+            offset += line.len() as u64 + 1;
+        }
+        printer.end_file().unwrap();
+        printer.finish().unwrap();
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn separates_groups() {
+        let contents = "1\n2 x\n3\n4\n5\n6\n7 x\n8";
+        let options = PrintOptions {
+            context: Context {
+                before: 1,
+                after: 1,
+            },
+            ..PrintOptions::default()
+        };
+        assert_eq!("1\n2 x\n3\n--\n6\n7 x\n8\n", print(contents, "x", options));
+    }
+
+    #[test]
+    fn merges_overlapping_windows() {
+        let contents = "1\n2 x\n3\n4 x\n5\n6";
+        let options = PrintOptions {
+            context: Context {
+                before: 2,
+                after: 2,
+            },
+            with_filename: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            "a.txt-1\na.txt:2 x\na.txt-3\na.txt:4 x\na.txt-5\na.txt-6\n",
+            print(contents, "x", options)
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let contents = "abc\nxx abc";
+        let options = PrintOptions {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!("1:1:0:abc\n2:4:4:xx abc\n", print(contents, "abc", options));
+    }
+
+    #[test]
+    fn highlights_every_match() {
+        let options = PrintOptions {
+            color: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            "\x1b[1;31mab\x1b[0m-\x1b[1;31mab\x1b[0m\n",
+            print("ab-ab", "ab", options)
+        );
+    }
+
+    #[test]
+    fn shows_matched_patterns() {
+        let options = PrintOptions {
+            show_pattern: true,
+            ..PrintOptions::default()
+        };
+        let mut printer = Printer::new(Vec::new(), options);
+        printer.set_patterns(Arc::new(vec!["foo".to_string(), "ba+r".to_string()]));
+        printer.begin_file(Path::new("a.txt"));
+        let matches = [
+            Submatch {
+                range: 0..4,
+                pattern: 1,
+            },
+            Submatch {
+                range: 5..8,
+                pattern: 0,
+            },
+            Submatch {
+                range: 9..12,
+                pattern: 1,
+            },
+        ];
+        printer.line(1, 0, b"baar foo bar", true, &matches).unwrap();
+        printer.end_file().unwrap();
+        assert_eq!(
+            "ba+r,foo:baar foo bar\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+
+    #[test]
+    fn binary_file_matches() {
+        let mut printer = Printer::new(Vec::new(), PrintOptions::default());
+        printer.begin_file(Path::new("a.bin"));
+        printer.line(1, 0, b"text x", true, &[]).unwrap();
+        printer.set_binary();
+        assert!(!printer.is_done());
+        printer.line(2, 7, b"\0 x", true, &[]).unwrap();
+        assert!(printer.is_done());
+        printer.line(3, 11, b"\0 x", true, &[]).unwrap();
+        printer.end_file().unwrap();
+        assert_eq!(
+            "text x\nBinary file a.bin matches\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_events() {
+        let options = PrintOptions {
+            json: true,
+            ..PrintOptions::default()
+        };
+        let expected = [
+            r#"{"type":"begin","data":{"path":{"text":"a.txt"}}}"#,
+            r#"{"type":"match","data":{"path":{"text":"a.txt"},"lines":{"text":"x \"ab\" ab"},"line_number":2,"absolute_offset":3,"submatches":[{"match":{"text":"ab"},"start":3,"end":5,"pattern":0},{"match":{"text":"ab"},"start":7,"end":9,"pattern":0}]}}"#,
+            r#"{"type":"end","data":{"path":{"text":"a.txt"},"stats":{"searches":1,"searches_with_match":1,"matched_lines":1,"matches":2}}}"#,
+            r#"{"type":"summary","data":{"stats":{"searches":1,"searches_with_match":1,"matched_lines":1,"matches":2}}}"#,
+        ];
+        assert_eq!(
+            expected.join("\n") + "\n",
+            print("no\nx \"ab\" ab\nno", "ab", options)
+        );
+    }
+
+    #[test]
+    fn count_and_file_lists() {
+        let with_mode = |mode| PrintOptions {
+            mode,
+            with_filename: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            "a.txt:2\n",
+            print("ab\nx\nab", "ab", with_mode(OutputMode::Count))
+        );
+        assert_eq!(
+            "a.txt\n",
+            print("ab", "ab", with_mode(OutputMode::FilesWithMatches))
+        );
+        assert_eq!(
+            "",
+            print("ab", "ab", with_mode(OutputMode::FilesWithoutMatch))
+        );
+        assert_eq!("", print("ab", "ab", with_mode(OutputMode::Quiet)));
+    }
+}
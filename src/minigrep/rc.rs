@@ -0,0 +1,136 @@
+use crate::minigrep::error::MinigrepError;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 用户级和项目级配置文件的文件名
+pub const RC_FILE: &str = ".minigreprc";
+
+/// 额外的默认参数, 按空白分开, 优先级在配置文件和命令行之间
+pub const OPTS_ENV: &str = "MINIGREP_OPTS";
+
+/// 一个参数是从哪来的, --debug-config 会列出来
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// 配置文件的第几行
+    File {
+        path: PathBuf,
+        line: usize,
+    },
+    Env(&'static str),
+    CommandLine,
+}
+
+impl Source {
+    pub fn is_command_line(&self) -> bool {
+        *self == Source::CommandLine
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File { path, line } => write!(f, "{}:{}", path.display(), line),
+            Source::Env(name) => write!(f, "environment variable {}", name),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// 按优先级从低到高排好的配置文件: 先是 ~/.minigreprc,
+/// 再是从当前目录往上找到的第一个 .minigreprc (和前一个是同一个文件时不重复)
+pub fn rc_files() -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+    if let Some(home) = home {
+        files.push(PathBuf::from(home).join(RC_FILE));
+    }
+    if let Ok(dir) = env::current_dir() {
+        let project = dir
+            .ancestors()
+            .map(|dir| dir.join(RC_FILE))
+            .find(|path| path.is_file());
+        if let Some(project) = project {
+            if !files.contains(&project) {
+                files.push(project);
+            }
+        }
+    }
+    files
+}
+
+/// 读一个配置文件, 不存在时返回 None
+pub fn read_rc(path: &Path) -> Result<Option<Vec<(String, Source)>>, MinigrepError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(parse_rc(path, &contents))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(MinigrepError::io(path, e)),
+    }
+}
+
+/// 每行一个参数, 和写在命令行上一样, 比如 `--smart-case` 或者 `--max-count=50`.
+/// 空行和 `#` 开头的行被忽略, 首尾的空白会去掉
+pub fn parse_rc(path: &Path, contents: &str) -> Vec<(String, Source)> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, arg)| {
+            let source = Source::File {
+                path: path.to_path_buf(),
+                line: line_number,
+            };
+            (arg.to_string(), source)
+        })
+        .collect()
+}
+
+/// 环境变量给出的参数: CASE_INSENSITIVE 相当于 --ignore-case, 然后是 MINIGREP_OPTS
+pub fn env_args() -> Vec<(String, Source)> {
+    let mut args = Vec::new();
+    if env::var_os("CASE_INSENSITIVE").is_some() {
+        args.push(("--ignore-case".to_string(), Source::Env("CASE_INSENSITIVE")));
+    }
+    if let Some(opts) = env::var_os(OPTS_ENV) {
+        let opts = opts.to_string_lossy().into_owned();
+        args.extend(
+            opts.split_whitespace()
+                .map(|arg| (arg.to_string(), Source::Env(OPTS_ENV))),
+        );
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rc_lines() {
+        let path = Path::new("/home/me/.minigreprc");
+        let args = parse_rc(path, "# defaults\n--smart-case\n\n  --max-count=50  \n");
+        assert_eq!(
+            vec![
+                (
+                    "--smart-case".to_string(),
+                    Source::File {
+                        path: path.to_path_buf(),
+                        line: 2
+                    }
+                ),
+                (
+                    "--max-count=50".to_string(),
+                    Source::File {
+                        path: path.to_path_buf(),
+                        line: 4
+                    }
+                ),
+            ],
+            args
+        );
+        assert_eq!("/home/me/.minigreprc:4", args[1].1.to_string());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::output::{Printer, Stats};
+use crate::minigrep::search::Input;
+use crate::Matcher;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+// 和 diff -u 默认的一样, 每段改动前后各带 3 行上下文
+const DIFF_CONTEXT: usize = 3;
+
+/// --replace 配合 --diff / --in-place 时怎么处理一个文件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Edit {
+    /// 输出统一格式的 diff
+    pub diff: bool,
+    /// 把替换后的内容写回文件
+    pub in_place: bool,
+}
+
+/// 对整个文件做替换, 按 edit 输出 diff 或者写回文件
+///
+/// 要写回就得拿到整个文件, 所以这里不逐行流式处理. 二进制文件不会被修改,
+/// 不是 UTF-8 的文件报错, 以免改坏原来的字节
+pub fn edit_input<W: Write>(
+    matcher: &Matcher,
+    input: &Input,
+    template: &str,
+    edit: Edit,
+    printer: &mut Printer<W>,
+) -> Result<Stats, MinigrepError> {
+    let path = match input {
+        Input::File(path) => path,
+        Input::Stdin if edit.in_place => {
+            return Err(MinigrepError::Usage(
+                "cannot edit standard input in place".to_string(),
+            ))
+        }
+        Input::Stdin => input.display_path(),
+    };
+    let mut stats = Stats {
+        searches: 1,
+        ..Stats::default()
+    };
+
+    // 不经过 Input::open, 写回去的必须是原样的字节, 包括 BOM
+    let mut bytes = Vec::new();
+    let read = match input {
+        Input::File(path) => File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)),
+        Input::Stdin => io::stdin().read_to_end(&mut bytes),
+    };
+    read.map_err(|e| MinigrepError::io(path, e))?;
+    if bytes.contains(&0) {
+        printer.file_output(&[], stats)?;
+        return Ok(stats);
+    }
+    let old = String::from_utf8(bytes).map_err(|e| {
+        let valid = &e.as_bytes()[..e.utf8_error().valid_up_to()];
+        MinigrepError::Encoding {
+            path: path.to_path_buf(),
+            line: valid.iter().filter(|&&b| b == b'\n').count() + 1,
+        }
+    })?;
+
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let mut new_lines = Vec::with_capacity(old_lines.len());
+    for line in &old_lines {
+        let content = line.trim_end_matches('\n');
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        // 空匹配也要替换, `^` 可以给每一行加上前缀
+        let matches = matcher.find_all(content);
+        if matches.is_empty() {
+            new_lines.push(line.to_string());
+            continue;
+        }
+        stats.searches_with_match = 1;
+        stats.matched_lines += 1;
+        stats.matches += matches.len();
+        let (mut replaced, _) = matcher.replace(content, &matches, template);
+        replaced.push_str(&line[content.len()..]);
+        new_lines.push(replaced);
+    }
+
+    // 统计也通过 file_output 交给 printer, 退出码要用
+    let mut diff = Vec::new();
+    if edit.diff {
+        write_diff(&mut diff, path, &old_lines, &new_lines)?;
+    }
+    printer.file_output(&diff, stats)?;
+    if edit.in_place && stats.matched_lines > 0 {
+        let new: String = new_lines.concat();
+        if new != old {
+            write_atomically(path, new.as_bytes()).map_err(|e| MinigrepError::io(path, e))?;
+        }
+    }
+    Ok(stats)
+}
+
+/// 统一格式的 diff, old 和 new 一一对应, 替换内容里的换行会让 new 的一项变成多行
+pub fn write_diff<W: Write>(
+    out: &mut W,
+    path: &Path,
+    old: &[&str],
+    new: &[String],
+) -> io::Result<()> {
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "--- {}", path.display())?;
+    writeln!(out, "+++ {}", path.display())?;
+
+    // 两处改动之间的距离不超过两倍上下文就放进同一段
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + DIFF_CONTEXT + 1).min(old.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // 前面的段增加的行数, 用来算新文件里的行号
+    let mut delta: isize = 0;
+    for (start, end) in hunks {
+        let new_len: usize = (start..end).map(|i| line_count(&new[i])).sum();
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            start + 1,
+            end - start,
+            start as isize + delta + 1,
+            new_len
+        )?;
+        delta += new_len as isize - (end - start) as isize;
+        for i in start..end {
+            if old[i] == new[i] {
+                write_diff_line(out, ' ', old[i])?;
+                continue;
+            }
+            write_diff_line(out, '-', old[i])?;
+            for line in new[i].split_inclusive('\n') {
+                write_diff_line(out, '+', line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn line_count(text: &str) -> usize {
+    text.split_inclusive('\n').count().max(1)
+}
+
+fn write_diff_line<W: Write>(out: &mut W, prefix: char, line: &str) -> io::Result<()> {
+    write!(out, "{}{}", prefix, line)?;
+    if !line.ends_with('\n') {
+        writeln!(out)?;
+        writeln!(out, "\\ No newline at end of file")?;
+    }
+    Ok(())
+}
+
+// 先写到同一目录下的临时文件再 rename, 中途出错原文件不会只写了一半
+//
+// path 是符号链接时改的是它指向的文件, 链接本身保持不变; 新文件沿用原来的权限和属主
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.minigrep-{}", name, process::id()));
+    let result = (|| {
+        let metadata = fs::metadata(&path)?;
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        file.write_all(contents)?;
+        copy_owner(&file, &metadata);
+        file.set_permissions(metadata.permissions())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// 尽量保留属主和属组, 普通用户改不了别人的文件属主, 这时只能算了
+#[cfg(unix)]
+fn copy_owner(file: &File, metadata: &fs::Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    let _ = fchown(file, Some(metadata.uid()), Some(metadata.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _metadata: &fs::Metadata) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn diff(old: &str, new: &str) -> String {
+        let old: Vec<&str> = old.split_inclusive('\n').collect();
+        let new: Vec<String> = new.split_inclusive('\n').map(String::from).collect();
+        let mut out = Vec::new();
+        write_diff(&mut out, Path::new("a.txt"), &old, &new).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn unified_diff_hunks() {
+        let old = "1\n2\n3\n4\nx\n6\n7\n8\n9\n10\n11\n12\n13\nx";
+        let new = "1\n2\n3\n4\ny\n6\n7\n8\n9\n10\n11\n12\n13\ny";
+        let expected = "\
+--- a.txt
++++ a.txt
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-x
++y
+ 6
+ 7
+ 8
+@@ -11,4 +11,4 @@
+ 11
+ 12
+ 13
+-x
+\\ No newline at end of file
++y
+\\ No newline at end of file
+";
+        assert_eq!(expected, diff(old, new));
+        assert_eq!("", diff("same\n", "same\n"));
+    }
+
+    #[test]
+    fn edits_file_in_place() {
+        let path = env::temp_dir().join(format!("minigrep-replace-{}.txt", process::id()));
+        fs::write(&path, "\u{feff}let a = foo(1);\r\nfoo\nbar\n").unwrap();
+        let matcher = Matcher::new(r"foo\((\d)\)", crate::MatchMode::Regex, true).unwrap();
+        let mut printer = Printer::new(Vec::new(), Default::default());
+        let edit = Edit {
+            diff: false,
+            in_place: true,
+        };
+        let stats = edit_input(
+            &matcher,
+            &Input::new(path.clone()),
+            "bar($1)",
+            edit,
+            &mut printer,
+        )
+        .unwrap();
+        assert_eq!(1, stats.matches);
+        assert_eq!(
+            "\u{feff}let a = bar(1);\r\nfoo\nbar\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn in_place_follows_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = env::temp_dir().join(format!("minigrep-replace-link-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "foo\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        write_atomically(&link, b"bar\n").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!("bar\n", fs::read_to_string(&target).unwrap());
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(0o640, mode & 0o777);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
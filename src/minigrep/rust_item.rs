@@ -0,0 +1,400 @@
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::output::{Printer, Stats};
+use crate::minigrep::search::Input;
+use proc_macro2::{Delimiter, TokenTree};
+use quote::ToTokens;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+use syn::visit::{self, Visit};
+
+/// --rust-item 能找的条目种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    /// 自由函数, impl 和 trait 里的方法
+    Fn,
+    Struct,
+    Enum,
+    Union,
+    Trait,
+    Mod,
+    /// macro_rules! 定义的宏
+    Macro,
+    Const,
+    Static,
+    /// type 别名, 以及 impl / trait 里的关联类型
+    Type,
+}
+
+const KINDS: &[(&str, ItemKind)] = &[
+    ("fn", ItemKind::Fn),
+    ("struct", ItemKind::Struct),
+    ("enum", ItemKind::Enum),
+    ("union", ItemKind::Union),
+    ("trait", ItemKind::Trait),
+    ("mod", ItemKind::Mod),
+    ("macro", ItemKind::Macro),
+    ("const", ItemKind::Const),
+    ("static", ItemKind::Static),
+    ("type", ItemKind::Type),
+];
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = KINDS
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .map_or("", |(name, _)| name);
+        write!(f, "{}", name)
+    }
+}
+
+/// --rust-item KIND:NAME, NAME 是 `*` 时匹配这一种的所有条目
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustItem {
+    pub kind: ItemKind,
+    pub name: String,
+}
+
+impl RustItem {
+    pub fn parse(value: &str) -> Result<RustItem, String> {
+        let (kind, name) = value
+            .split_once(':')
+            .filter(|(_, name)| !name.is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "invalid value '{}' for '--rust-item' (expected KIND:NAME)",
+                    value
+                )
+            })?;
+        let kind = KINDS
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| {
+                let names: Vec<&str> = KINDS.iter().map(|(name, _)| *name).collect();
+                format!(
+                    "unknown item kind '{}' (expected one of {})",
+                    kind,
+                    names.join(", ")
+                )
+            })?;
+        Ok(RustItem {
+            kind,
+            name: name.to_string(),
+        })
+    }
+
+    fn matches(&self, kind: ItemKind, ident: &syn::Ident) -> bool {
+        self.kind == kind && (self.name == "*" || ident == self.name.as_str())
+    }
+}
+
+/// 找到的一个条目
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemMatch {
+    pub kind: ItemKind,
+    /// 从 1 开始, 包括首尾两行, 不算前面的属性和文档注释
+    pub lines: (usize, usize),
+    /// 带上所在模块的完整路径, 方法前面是 impl 的类型或者 trait 名
+    pub path: String,
+}
+
+/// 解析源码, 按出现的顺序返回匹配的条目
+///
+/// 比较的是语法树, 注释和字符串里出现的名字不算. module 是这个文件的模块路径
+pub fn find_items(query: &RustItem, module: &str, source: &str) -> syn::Result<Vec<ItemMatch>> {
+    let file = syn::parse_file(source)?;
+    let mut finder = Finder {
+        query,
+        path: vec![module.to_string()],
+        found: Vec::new(),
+    };
+    finder.visit_file(&file);
+    Ok(finder.found)
+}
+
+/// 读入一个文件找条目, 每个条目交给 printer 输出一行
+pub fn search_input<W: Write>(
+    query: &RustItem,
+    input: &Input,
+    printer: &mut Printer<W>,
+) -> Result<Stats, MinigrepError> {
+    let path = input.display_path();
+    let mut source = String::new();
+    input
+        .open()
+        .and_then(|mut reader| reader.read_to_string(&mut source))
+        .map_err(|e| MinigrepError::io(path, e))?;
+    let items = find_items(query, &module_path(path), &source).map_err(|e| {
+        let start = e.span().start();
+        let message = format!(
+            "cannot parse as Rust: line {} column {}: {}",
+            start.line,
+            start.column + 1,
+            e
+        );
+        MinigrepError::io(path, io::Error::new(io::ErrorKind::InvalidData, message))
+    })?;
+
+    printer.begin_file(path);
+    for item in items {
+        printer.item(item.lines, &format!("{} {}", item.kind, item.path))?;
+    }
+    Ok(printer.end_file()?)
+}
+
+// 按 cargo 的目录约定从文件路径推出模块路径: src/minigrep/args.rs 是 crate::minigrep::args,
+// lib.rs, main.rs, mod.rs 和 src/bin 下的文件不增加一层
+fn module_path(path: &Path) -> String {
+    let components: Vec<&str> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    let after_src = match components.iter().rposition(|&c| c == "src") {
+        Some(i) => &components[i + 1..],
+        None => &components[components.len().saturating_sub(1)..],
+    };
+    let mut module = String::from("crate");
+    if after_src.first() == Some(&"bin") {
+        return module;
+    }
+    for (i, name) in after_src.iter().enumerate() {
+        let name = if i + 1 == after_src.len() {
+            match name.strip_suffix(".rs") {
+                Some("lib") | Some("main") | Some("mod") => break,
+                Some(stem) => stem,
+                None => name,
+            }
+        } else {
+            name
+        };
+        module.push_str("::");
+        module.push_str(name);
+    }
+    module
+}
+
+struct Finder<'q> {
+    query: &'q RustItem,
+    // 从文件的模块开始, 进入 mod / impl / trait 时压入
+    path: Vec<String>,
+    found: Vec<ItemMatch>,
+}
+
+impl Finder<'_> {
+    fn check<T: ToTokens>(&mut self, kind: ItemKind, ident: &syn::Ident, node: &T) {
+        if !self.query.matches(kind, ident) {
+            return;
+        }
+        if let Some(lines) = line_span(node) {
+            self.found.push(ItemMatch {
+                kind,
+                lines,
+                path: format!("{}::{}", self.path.join("::"), ident),
+            });
+        }
+    }
+
+    fn nested<F: FnOnce(&mut Self)>(&mut self, name: String, f: F) {
+        self.path.push(name);
+        f(self);
+        self.path.pop();
+    }
+}
+
+// 跳过开头的属性 (文档注释也是 #[doc = ...]), 取第一个和最后一个 token 所在的行
+fn line_span<T: ToTokens>(node: &T) -> Option<(usize, usize)> {
+    let tokens: Vec<TokenTree> = node.to_token_stream().into_iter().collect();
+    let mut rest = &tokens[..];
+    while let [TokenTree::Punct(pound), TokenTree::Group(group), tail @ ..] = rest {
+        if pound.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+            break;
+        }
+        rest = tail;
+    }
+    let start = rest.first()?.span().start().line;
+    let end = rest.last()?.span().end().line;
+    Some((start, end))
+}
+
+// impl Foo<T> for Bar 里的 Bar, 不是简单路径的类型就用它的源码
+fn type_name(ty: &syn::Type) -> String {
+    match ty {
+        syn::Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map_or_else(String::new, |segment| segment.ident.to_string()),
+        _ => ty.to_token_stream().to_string().replace(' ', ""),
+    }
+}
+
+impl<'ast> Visit<'ast> for Finder<'_> {
+    fn visit_item_fn(&mut self, i: &'ast syn::ItemFn) {
+        self.check(ItemKind::Fn, &i.sig.ident, i);
+        visit::visit_item_fn(self, i);
+    }
+
+    fn visit_impl_item_method(&mut self, i: &'ast syn::ImplItemMethod) {
+        self.check(ItemKind::Fn, &i.sig.ident, i);
+        visit::visit_impl_item_method(self, i);
+    }
+
+    fn visit_trait_item_method(&mut self, i: &'ast syn::TraitItemMethod) {
+        self.check(ItemKind::Fn, &i.sig.ident, i);
+        visit::visit_trait_item_method(self, i);
+    }
+
+    fn visit_item_struct(&mut self, i: &'ast syn::ItemStruct) {
+        self.check(ItemKind::Struct, &i.ident, i);
+        visit::visit_item_struct(self, i);
+    }
+
+    fn visit_item_enum(&mut self, i: &'ast syn::ItemEnum) {
+        self.check(ItemKind::Enum, &i.ident, i);
+        visit::visit_item_enum(self, i);
+    }
+
+    fn visit_item_union(&mut self, i: &'ast syn::ItemUnion) {
+        self.check(ItemKind::Union, &i.ident, i);
+        visit::visit_item_union(self, i);
+    }
+
+    fn visit_item_trait(&mut self, i: &'ast syn::ItemTrait) {
+        self.check(ItemKind::Trait, &i.ident, i);
+        self.nested(i.ident.to_string(), |this| visit::visit_item_trait(this, i));
+    }
+
+    fn visit_item_impl(&mut self, i: &'ast syn::ItemImpl) {
+        self.nested(type_name(&i.self_ty), |this| {
+            visit::visit_item_impl(this, i)
+        });
+    }
+
+    fn visit_item_mod(&mut self, i: &'ast syn::ItemMod) {
+        self.check(ItemKind::Mod, &i.ident, i);
+        self.nested(i.ident.to_string(), |this| visit::visit_item_mod(this, i));
+    }
+
+    fn visit_item_macro(&mut self, i: &'ast syn::ItemMacro) {
+        if let Some(ident) = &i.ident {
+            self.check(ItemKind::Macro, ident, i);
+        }
+        visit::visit_item_macro(self, i);
+    }
+
+    fn visit_item_const(&mut self, i: &'ast syn::ItemConst) {
+        self.check(ItemKind::Const, &i.ident, i);
+        visit::visit_item_const(self, i);
+    }
+
+    fn visit_impl_item_const(&mut self, i: &'ast syn::ImplItemConst) {
+        self.check(ItemKind::Const, &i.ident, i);
+        visit::visit_impl_item_const(self, i);
+    }
+
+    fn visit_trait_item_const(&mut self, i: &'ast syn::TraitItemConst) {
+        self.check(ItemKind::Const, &i.ident, i);
+        visit::visit_trait_item_const(self, i);
+    }
+
+    fn visit_item_static(&mut self, i: &'ast syn::ItemStatic) {
+        self.check(ItemKind::Static, &i.ident, i);
+        visit::visit_item_static(self, i);
+    }
+
+    fn visit_item_type(&mut self, i: &'ast syn::ItemType) {
+        self.check(ItemKind::Type, &i.ident, i);
+        visit::visit_item_type(self, i);
+    }
+
+    fn visit_impl_item_type(&mut self, i: &'ast syn::ImplItemType) {
+        self.check(ItemKind::Type, &i.ident, i);
+        visit::visit_impl_item_type(self, i);
+    }
+
+    fn visit_trait_item_type(&mut self, i: &'ast syn::TraitItemType) {
+        self.check(ItemKind::Type, &i.ident, i);
+        visit::visit_trait_item_type(self, i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+// fn search 只是注释
+mod limits {
+    /// 文档注释不算在行号里
+    pub struct Config {
+        max: usize,
+    }
+
+    impl Config {
+        pub fn search(&self) -> &str {
+            "fn search() {}"
+        }
+    }
+}
+
+pub trait Messenger {
+    fn send(&self, msg: &str);
+}
+
+macro_rules! recurrence {
+    () => {};
+}
+
+fn search() {}
+"#;
+
+    fn find(query: &str) -> Vec<(usize, usize, String)> {
+        let query = RustItem::parse(query).unwrap();
+        find_items(&query, "crate::notes", SOURCE)
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.lines.0, item.lines.1, item.path))
+            .collect()
+    }
+
+    #[test]
+    fn finds_items_by_kind_and_name() {
+        assert_eq!(
+            vec![
+                (10, 12, "crate::notes::limits::Config::search".to_string()),
+                (24, 24, "crate::notes::search".to_string()),
+            ],
+            find("fn:search")
+        );
+        assert_eq!(
+            vec![(5, 7, "crate::notes::limits::Config".to_string())],
+            find("struct:Config")
+        );
+        assert_eq!(3, find("fn:*").len());
+        assert_eq!(16, find("trait:Messenger")[0].0);
+        assert_eq!(20, find("macro:recurrence")[0].0);
+        assert!(find("enum:Config").is_empty());
+        assert!(RustItem::parse("function:main").is_err());
+        assert!(RustItem::parse("fn").is_err());
+    }
+
+    #[test]
+    fn module_paths() {
+        assert_eq!(
+            "crate::minigrep::args",
+            module_path(Path::new("src/minigrep/args.rs"))
+        );
+        assert_eq!(
+            "crate::minigrep",
+            module_path(Path::new("./src/minigrep/mod.rs"))
+        );
+        assert_eq!("crate", module_path(Path::new("threadPool/src/lib.rs")));
+        assert_eq!("crate", module_path(Path::new("src/bin/minigrep.rs")));
+        assert_eq!("crate::build", module_path(Path::new("build.rs")));
+    }
+}
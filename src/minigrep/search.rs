@@ -0,0 +1,359 @@
+use crate::minigrep::encoding;
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::fuzzy::{self, Fuzzy};
+use crate::minigrep::output::{Printer, Stats};
+use crate::minigrep::replace::{self, Edit};
+use crate::minigrep::rust_item::{self, RustItem};
+use crate::minigrep::tar;
+use crate::Matcher;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+
+// 命令行上用 `-` 表示标准输入
+const STDIN_PATH: &str = "-";
+
+/// 要搜索的一个输入: 文件或者标准输入
+pub enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    pub fn new(path: PathBuf) -> Input {
+        if path.as_os_str() == STDIN_PATH {
+            Input::Stdin
+        } else {
+            Input::File(path)
+        }
+    }
+
+    /// 输出时用的名字, 和 grep 一样标准输入显示成 (standard input)
+    pub fn display_path(&self) -> &Path {
+        match self {
+            Input::Stdin => Path::new("(standard input)"),
+            Input::File(path) => path,
+        }
+    }
+
+    /// 打开成 BufRead, 之后一行一行地读, 不会把整个文件读进内存
+    ///
+    /// 带 BOM 的 UTF-16 会被转成 UTF-8, 这时的字节偏移是转码之后的
+    pub fn open(&self) -> io::Result<Box<dyn BufRead>> {
+        let reader: Box<dyn BufRead> = match self {
+            Input::Stdin => Box::new(BufReader::new(io::stdin())),
+            Input::File(path) => Box::new(BufReader::with_capacity(64 * 1024, File::open(path)?)),
+        };
+        encoding::decode_bom(reader)
+    }
+}
+
+/// 含有 NUL 字节的文件怎么处理, 和 grep 的 --binary-files 一样
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryFiles {
+    /// 照常搜索, 但只输出 "Binary file X matches"
+    #[default]
+    Binary,
+    /// -a 当作文本, 匹配的行照常输出
+    Text,
+    /// -I 当作没有匹配, 不再往下读
+    WithoutMatch,
+}
+
+/// 影响哪些行被选中的选项
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// -v 选中没有匹配的行
+    pub invert: bool,
+    /// -m 每个文件最多选中多少行
+    pub max_count: Option<usize>,
+    pub binary: BinaryFiles,
+    /// 不是 UTF-8 的字节换成 U+FFFD 继续搜索, 否则这个文件报错
+    pub lossy: bool,
+    /// --replace 的模板, 输出的匹配行是替换之后的
+    pub replace: Option<Arc<str>>,
+    /// 替换结果不是逐行输出, 而是 diff 或者写回文件
+    pub edit: Edit,
+    /// --fuzzy: 不用 matcher, 按得分排序输出
+    pub fuzzy: Option<Arc<Fuzzy>>,
+    /// --rust-item: 不按行搜索, 解析 Rust 源码找条目
+    pub rust_item: Option<Arc<RustItem>>,
+}
+
+/// 在一个输入里搜索, 结果交给 printer 输出
+///
+/// 读输入出的错带着这个输入的路径, 写 printer 出的错没有路径.
+/// 压缩包里某一项出的错不会中断整个包, 而是放进 skipped, 由调用方和其他文件的错误一起报告
+pub fn search_input<W: Write>(
+    matcher: &Matcher,
+    input: &Input,
+    options: &SearchOptions,
+    printer: &mut Printer<W>,
+    skipped: &mut Vec<MinigrepError>,
+) -> Result<Stats, MinigrepError> {
+    if let Some(query) = &options.rust_item {
+        return rust_item::search_input(query, input, printer);
+    }
+    let editing = options.replace.is_some() && options.edit != Edit::default();
+    if let Input::File(path) = input {
+        if tar::is_archive(path) {
+            if editing {
+                return Err(MinigrepError::Usage(format!(
+                    "{}: cannot edit files inside an archive",
+                    path.display()
+                )));
+            }
+            return tar::search_archive(matcher, path, options, printer, skipped);
+        }
+    }
+    if let (Some(template), true) = (&options.replace, editing) {
+        return replace::edit_input(matcher, input, template, options.edit, printer);
+    }
+    let path = input.display_path();
+    let reader = input.open().map_err(|e| MinigrepError::io(path, e))?;
+    search_reader(matcher, path, reader, options, printer)
+}
+
+/// 和 search_input 一样, 但内容来自已经打开的 reader, path 只用来输出和报错
+///
+/// 压缩包里的文件没有自己的路径, 用的是 `archive.tar:inner/path`
+pub fn search_reader<R: BufRead, W: Write>(
+    matcher: &Matcher,
+    path: &Path,
+    mut reader: R,
+    options: &SearchOptions,
+    printer: &mut Printer<W>,
+) -> Result<Stats, MinigrepError> {
+    if let Some(fuzzy) = &options.fuzzy {
+        return fuzzy::search_reader(fuzzy, path, reader, options, printer);
+    }
+    let read_error = |e| MinigrepError::io(path, e);
+    printer.begin_file(path);
+    // 先看第一块缓冲区, 大部分二进制文件开头就有 NUL
+    let mut binary = reader.fill_buf().map_err(read_error)?.contains(&0);
+    if binary {
+        match options.binary {
+            BinaryFiles::WithoutMatch => return Ok(printer.end_file()?),
+            BinaryFiles::Binary => printer.set_binary(),
+            BinaryFiles::Text => {}
+        }
+    }
+    let mut selected = 0;
+    // 回调里的错误先记下来, for_each_line_bytes 自己返回的就只有读输入的错误
+    let mut failure = None;
+    let result = for_each_line_bytes(reader, |line_number, offset, bytes| {
+        // NUL 也可能出现在文件后面, 从那一行起按二进制处理
+        if !binary && bytes.contains(&0) {
+            binary = true;
+            match options.binary {
+                BinaryFiles::WithoutMatch => return Ok(false),
+                BinaryFiles::Binary => printer.set_binary(),
+                BinaryFiles::Text => {}
+            }
+        }
+        // 二进制文件本来就不是文本, 总是宽松地解码
+        let line = if binary || options.lossy {
+            String::from_utf8_lossy(bytes)
+        } else {
+            match str::from_utf8(bytes) {
+                Ok(line) => Cow::Borrowed(line),
+                Err(_) => {
+                    failure = Some(MinigrepError::Encoding {
+                        path: path.to_path_buf(),
+                        line: line_number,
+                    });
+                    return Ok(false);
+                }
+            }
+        };
+
+        // 达到 -m 的上限后只把剩下的后文输出完, 之后就不用再读了
+        let limit_reached = options.max_count.is_some_and(|max| selected >= max);
+        if limit_reached && !printer.in_after_context() {
+            return Ok(false);
+        }
+
+        // 选不选中看 is_match, find_iter 只给出要高亮的非空匹配, `^$` 这样的空匹配也算匹配
+        let is_match = !limit_reached && matcher.is_match(&line) != options.invert;
+        if is_match {
+            selected += 1;
+        }
+        // -v 选中的行本身没有匹配, 不需要高亮
+        let mut matches = if is_match && !options.invert && options.replace.is_none() {
+            matcher.find_iter(&line)
+        } else {
+            Vec::new()
+        };
+        let written = match &options.replace {
+            // 替换时空匹配也算, `^` 和 `$` 才能在行首行尾插入内容
+            Some(template) if is_match && !options.invert => {
+                let (replaced, spans) = matcher.replace(&line, &matcher.find_all(&line), template);
+                printer.line(line_number, offset, replaced.as_bytes(), true, &spans)
+            }
+            _ => {
+                // printer 要的是原始字节里的位置, 宽松解码换过字节时要换算回去
+                if let Cow::Owned(_) = line {
+                    for m in &mut matches {
+                        m.range = encoding::raw_offset(bytes, m.range.start)
+                            ..encoding::raw_offset(bytes, m.range.end);
+                    }
+                }
+                printer.line(line_number, offset, bytes, is_match, &matches)
+            }
+        };
+        if let Err(e) = written {
+            failure = Some(MinigrepError::from(e));
+            return Ok(false);
+        }
+        Ok(!printer.is_done())
+    });
+    // 中途出错也要结束这个文件, JSON 的 begin 和 end 才能配对
+    let stats = printer.end_file()?;
+    result.map_err(read_error)?;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(stats),
+    }
+}
+
+/// 逐行读取 reader, 回调拿到 (行号, 行首字节偏移, 去掉换行符的内容)
+///
+/// 回调返回 false 时停止读取. 读缓冲区在各行之间复用, 内存占用只和最长的一行有关
+pub fn for_each_line<R, F>(reader: R, mut f: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(usize, u64, &str) -> io::Result<bool>,
+{
+    for_each_line_bytes(reader, |line_number, offset, line| {
+        let line = str::from_utf8(line).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: stream did not contain valid UTF-8", line_number),
+            )
+        })?;
+        f(line_number, offset, line)
+    })
+}
+
+/// 和 for_each_line 一样, 但不检查 UTF-8, 由调用方决定怎么解码
+pub fn for_each_line_bytes<R, F>(mut reader: R, mut f: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(usize, u64, &[u8]) -> io::Result<bool>,
+{
+    let mut buf = Vec::new();
+    let mut line_number = 0;
+    let mut offset = 0;
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+
+        let mut line = &buf[..];
+        if let Some(rest) = line.strip_suffix(b"\n") {
+            line = rest.strip_suffix(b"\r").unwrap_or(rest);
+        }
+        if !f(line_number, offset, line)? {
+            return Ok(());
+        }
+        offset += read as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minigrep::output::PrintOptions;
+    use crate::MatchMode;
+    use std::io::Cursor;
+
+    #[test]
+    fn lines_with_offsets() {
+        let reader = Cursor::new("one\r\ntwo\n\nlast");
+        let mut lines = Vec::new();
+        for_each_line(reader, |n, offset, line| {
+            lines.push((n, offset, line.to_string()));
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(
+            vec![
+                (1, 0, "one".to_string()),
+                (2, 5, "two".to_string()),
+                (3, 9, "".to_string()),
+                (4, 10, "last".to_string()),
+            ],
+            lines
+        );
+    }
+
+    // 按 PATTERN 搜索 input, 返回选中的行号
+    fn selected(pattern: &str, invert: bool, input: &str) -> Vec<usize> {
+        let matcher = Matcher::with_patterns(&[pattern], MatchMode::Regex, true).unwrap();
+        let options = SearchOptions {
+            invert,
+            ..SearchOptions::default()
+        };
+        let print_options = PrintOptions {
+            line_number: true,
+            ..PrintOptions::default()
+        };
+        let mut printer = Printer::new(Vec::new(), print_options);
+        search_reader(
+            &matcher,
+            Path::new("f"),
+            Cursor::new(input),
+            &options,
+            &mut printer,
+        )
+        .unwrap();
+        String::from_utf8(printer.into_inner())
+            .unwrap()
+            .lines()
+            .map(|line| line.split(':').next().unwrap().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn zero_width_matches_select_lines() {
+        let input = "one\n\nthree\n";
+        assert_eq!(vec![2], selected("^$", false, input));
+        assert_eq!(vec![1, 3], selected("^$", true, input));
+        assert_eq!(vec![1, 2, 3], selected("^", false, input));
+        assert_eq!(vec![1, 2, 3], selected("", false, input));
+        assert_eq!(Vec::<usize>::new(), selected("x*", true, input));
+    }
+
+    #[test]
+    fn json_keeps_raw_bytes() {
+        let matcher = Matcher::with_patterns(&["ok"], MatchMode::Literal, true).unwrap();
+        let options = SearchOptions {
+            lossy: true,
+            ..SearchOptions::default()
+        };
+        let print_options = PrintOptions {
+            json: true,
+            ..PrintOptions::default()
+        };
+        let mut printer = Printer::new(Vec::new(), print_options);
+        let input = Cursor::new(b"caf\xe9 ok\n".to_vec());
+        search_reader(&matcher, Path::new("f"), input, &options, &mut printer).unwrap();
+        let output = String::from_utf8(printer.into_inner()).unwrap();
+        // 整行是原始字节的 base64, 位置按原始字节算
+        assert!(output.contains(r#""lines":{"bytes":"Y2Fm6SBvaw=="}"#));
+        assert!(output.contains(r#""submatches":[{"match":{"text":"ok"},"start":5,"end":7"#));
+    }
+
+    #[test]
+    fn invalid_utf8_is_error() {
+        let reader = Cursor::new(b"ok\n\xff\xfe\n".to_vec());
+        let err = for_each_line(reader, |_, _, _| Ok(true)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
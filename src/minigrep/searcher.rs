@@ -0,0 +1,361 @@
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::matcher::{CaseMode, MatchMode, Matcher};
+use crate::minigrep::search::Input;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::str::Lines;
+use std::sync::Arc;
+
+/// 一处匹配. 一行里有多处匹配时会得到多个 Match, 它们的 line 相同
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    /// 从 1 开始
+    pub line_number: usize,
+    /// 匹配在 line 中的字节范围
+    pub byte_range: Range<usize>,
+    /// 整行内容, 不含换行符. 搜索 &str 时是借用的, 不会复制
+    pub line: Line<'a>,
+}
+
+/// Match 所在的整行, 用起来和 &str 一样
+///
+/// 从 reader 读的行每行只复制一次, 同一行的多个 Match 共用它
+#[derive(Clone, PartialEq, Eq)]
+pub enum Line<'a> {
+    Borrowed(&'a str),
+    Shared(Arc<str>),
+}
+
+impl Deref for Line<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Line::Borrowed(line) => line,
+            Line::Shared(line) => line,
+        }
+    }
+}
+
+impl AsRef<str> for Line<'_> {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl fmt::Debug for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl Match<'_> {
+    /// 匹配到的文本
+    pub fn as_str(&self) -> &str {
+        &self.line[self.byte_range.clone()]
+    }
+}
+
+/// 给把 minigrep 当作库来用的程序: 构建一次, 之后在字符串, reader 或者文件上
+/// 惰性地一个一个取匹配, 随时可以停下来, 中间不会攒出 Vec
+///
+/// ```
+/// use the_rust_programming_language::{CaseMode, Searcher};
+///
+/// let searcher = Searcher::builder()
+///     .pattern("rust")
+///     .case(CaseMode::Insensitive)
+///     .build()
+///     .unwrap();
+/// let first = searcher.search_str("safe\nTrust me\n").next().unwrap();
+/// assert_eq!((2, 1..5), (first.line_number, first.byte_range));
+/// ```
+pub struct Searcher {
+    matcher: Matcher,
+}
+
+/// Searcher 的各项设置, 没有设置的和命令行的默认值一样
+#[derive(Debug, Clone, Default)]
+pub struct SearcherBuilder {
+    patterns: Vec<String>,
+    case: CaseMode,
+    mode: MatchMode,
+    whole_word: bool,
+}
+
+impl SearcherBuilder {
+    /// 可以调用多次, 任意一个模式匹配就算匹配
+    pub fn pattern(mut self, pattern: &str) -> SearcherBuilder {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn case(mut self, case: CaseMode) -> SearcherBuilder {
+        self.case = case;
+        self
+    }
+
+    /// 默认按字面量匹配
+    pub fn match_mode(mut self, mode: MatchMode) -> SearcherBuilder {
+        self.mode = mode;
+        self
+    }
+
+    pub fn whole_word(mut self, yes: bool) -> SearcherBuilder {
+        self.whole_word = yes;
+        self
+    }
+
+    /// 正则写错时返回 MinigrepError::Pattern
+    pub fn build(self) -> Result<Searcher, MinigrepError> {
+        let matcher = Matcher::with_patterns(
+            &self.patterns,
+            self.mode,
+            self.case.is_sensitive(&self.patterns),
+        )?
+        .whole_word(self.whole_word);
+        Ok(Searcher { matcher })
+    }
+}
+
+impl Searcher {
+    pub fn builder() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    pub fn search_str<'a>(&'a self, text: &'a str) -> StrMatches<'a> {
+        StrMatches {
+            matcher: &self.matcher,
+            lines: text.lines(),
+            line: None,
+            line_number: 0,
+            start: 0,
+            matched: false,
+        }
+    }
+
+    /// 一次只读一行, 读出错或者遇到不是 UTF-8 的行时产生一个 Err, 之后迭代结束
+    pub fn search_reader<R: BufRead>(&self, reader: R) -> ReaderMatches<'_, R> {
+        ReaderMatches {
+            matcher: &self.matcher,
+            reader,
+            line: String::new(),
+            shared: None,
+            has_line: false,
+            line_number: 0,
+            start: 0,
+            matched: false,
+            done: false,
+        }
+    }
+
+    /// 和命令行一样, 带 BOM 的 UTF-16 文件会被转成 UTF-8
+    pub fn search_path(
+        &self,
+        path: &Path,
+    ) -> Result<ReaderMatches<'_, Box<dyn BufRead>>, MinigrepError> {
+        let reader = Input::File(path.to_path_buf())
+            .open()
+            .map_err(|e| MinigrepError::io(path, e))?;
+        Ok(self.search_reader(reader))
+    }
+}
+
+// 一行里的下一个非空匹配; 一个都没有但这一行能匹配时 (比如 `^$` 和空行),
+// 产生一个空的匹配, 这一行才不会被漏掉
+fn next_in_line(
+    matcher: &Matcher,
+    line: &str,
+    start: &mut usize,
+    matched: &mut bool,
+) -> Option<Range<usize>> {
+    if let Some(m) = matcher.next_match(line, start) {
+        *matched = true;
+        return Some(m.range);
+    }
+    if *matched {
+        return None;
+    }
+    *matched = true;
+    matcher.find_at(line, 0).map(|m| m.range)
+}
+
+/// Searcher::search_str 返回的迭代器
+pub struct StrMatches<'a> {
+    matcher: &'a Matcher,
+    lines: Lines<'a>,
+    line: Option<&'a str>,
+    line_number: usize,
+    // 当前行里下一次开始找的位置
+    start: usize,
+    // 当前行已经产生过匹配
+    matched: bool,
+}
+
+impl<'a> Iterator for StrMatches<'a> {
+    type Item = Match<'a>;
+
+    fn next(&mut self) -> Option<Match<'a>> {
+        loop {
+            if let Some(line) = self.line {
+                if let Some(range) =
+                    next_in_line(self.matcher, line, &mut self.start, &mut self.matched)
+                {
+                    return Some(Match {
+                        line_number: self.line_number,
+                        byte_range: range,
+                        line: Line::Borrowed(line),
+                    });
+                }
+            }
+            self.line = Some(self.lines.next()?);
+            self.line_number += 1;
+            self.start = 0;
+            self.matched = false;
+        }
+    }
+}
+
+/// Searcher::search_reader 返回的迭代器
+pub struct ReaderMatches<'s, R> {
+    matcher: &'s Matcher,
+    reader: R,
+    // 读缓冲区在各行之间复用
+    line: String,
+    // 当前行第一次匹配时复制一份, 这一行后面的匹配都用它
+    shared: Option<Arc<str>>,
+    has_line: bool,
+    line_number: usize,
+    start: usize,
+    matched: bool,
+    done: bool,
+}
+
+impl<R: BufRead> Iterator for ReaderMatches<'_, R> {
+    type Item = io::Result<Match<'static>>;
+
+    fn next(&mut self) -> Option<io::Result<Match<'static>>> {
+        while !self.done {
+            if self.has_line {
+                if let Some(range) =
+                    next_in_line(self.matcher, &self.line, &mut self.start, &mut self.matched)
+                {
+                    let text = &self.line;
+                    let line = self.shared.get_or_insert_with(|| Arc::from(text.as_str()));
+                    return Some(Ok(Match {
+                        line_number: self.line_number,
+                        byte_range: range,
+                        line: Line::Shared(Arc::clone(line)),
+                    }));
+                }
+            }
+
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => self.done = true,
+                Ok(_) => {
+                    if self.line.ends_with('\n') {
+                        self.line.pop();
+                        if self.line.ends_with('\r') {
+                            self.line.pop();
+                        }
+                    }
+                    self.has_line = true;
+                    self.shared = None;
+                    self.line_number += 1;
+                    self.start = 0;
+                    self.matched = false;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn lazy_over_str() {
+        let searcher = Searcher::builder()
+            .pattern("o")
+            .pattern("xyz")
+            .build()
+            .unwrap();
+        let text = "one two\nnothing\nxyz";
+        let found: Vec<(usize, Range<usize>)> = searcher
+            .search_str(text)
+            .map(|m| (m.line_number, m.byte_range))
+            .collect();
+        assert_eq!(vec![(1, 0..1), (1, 6..7), (2, 1..2), (3, 0..3)], found);
+
+        // 取到第二个就停, 后面的行不会被搜索
+        let first = searcher.search_str(text).nth(1).unwrap();
+        assert!(matches!(first.line, Line::Borrowed("one two")));
+        assert_eq!("o", first.as_str());
+    }
+
+    #[test]
+    fn over_reader() {
+        let searcher = Searcher::builder()
+            .pattern(r"\d+")
+            .match_mode(MatchMode::Regex)
+            .build()
+            .unwrap();
+        let reader = Cursor::new(b"a1\r\nb22\n\xff 3\n".to_vec());
+        let mut matches = searcher.search_reader(reader);
+        assert_eq!("1", matches.next().unwrap().unwrap().as_str());
+        let m = matches.next().unwrap().unwrap();
+        assert_eq!((2, "b22"), (m.line_number, m.line.as_ref()));
+        assert!(matches.next().unwrap().is_err());
+        assert!(matches.next().is_none());
+
+        // 同一行的多个匹配共用一份内容
+        let reader = Cursor::new("1 22 333\n");
+        let found: Vec<Match> = searcher.search_reader(reader).map(Result::unwrap).collect();
+        match (&found[0].line, &found[2].line) {
+            (Line::Shared(a), Line::Shared(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected shared lines"),
+        }
+    }
+
+    #[test]
+    fn zero_width_matches() {
+        let searcher = Searcher::builder()
+            .pattern("^$")
+            .match_mode(MatchMode::Regex)
+            .build()
+            .unwrap();
+        let blank: Vec<(usize, Range<usize>)> = searcher
+            .search_str("one\n\nthree\n")
+            .map(|m| (m.line_number, m.byte_range))
+            .collect();
+        assert_eq!(vec![(2, 0..0)], blank);
+        let reader = Cursor::new("a\n\n");
+        assert_eq!(1, searcher.search_reader(reader).count());
+    }
+
+    #[test]
+    fn smart_case() {
+        let searcher = Searcher::builder()
+            .pattern("rust")
+            .case(CaseMode::Smart)
+            .build()
+            .unwrap();
+        assert_eq!(2, searcher.search_str("Rust\nrust").count());
+        let searcher = Searcher::builder()
+            .pattern("Rust")
+            .case(CaseMode::Smart)
+            .build()
+            .unwrap();
+        assert_eq!(1, searcher.search_str("RUST\nRust").count());
+    }
+}
@@ -0,0 +1,425 @@
+use crate::minigrep::encoding;
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::output::{Printer, Stats};
+use crate::minigrep::search::{self, SearchOptions};
+use crate::Matcher;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+const BLOCK: u64 = 512;
+
+/// 按扩展名判断, 当作 tar 包打开, 搜索里面的每个普通文件
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("tar"))
+}
+
+/// tar 包里一项的类型, 只有 File 会被搜索
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    /// 链接, 设备文件等
+    Other,
+}
+
+/// 顺序读取 ustar / pax 格式的 tar 包, 不需要 Seek, 也可以读管道
+pub struct Archive<R> {
+    reader: R,
+    // 当前这一项还没被读走的数据, 以及之后凑满 512 字节的填充
+    remaining: u64,
+    padding: u64,
+    done: bool,
+}
+
+/// tar 包里的一项, 实现了 Read, 读到的是这一项的内容
+pub struct Entry<'a, R> {
+    pub path: String,
+    pub kind: EntryKind,
+    reader: &'a mut R,
+    remaining: &'a mut u64,
+}
+
+// 一个头部块里解析出来的字段, 路径和大小可能被前面的 pax / GNU 长文件名覆盖
+struct Header {
+    path: String,
+    size: u64,
+    typeflag: u8,
+}
+
+impl<R: Read> Archive<R> {
+    pub fn new(reader: R) -> Archive<R> {
+        Archive {
+            reader,
+            remaining: 0,
+            padding: 0,
+            done: false,
+        }
+    }
+
+    /// 下一个文件, 读到结尾的全零块时返回 None
+    ///
+    /// 上一项没读完的内容会被跳过
+    pub fn next_entry(&mut self) -> io::Result<Option<Entry<'_, R>>> {
+        // pax 扩展头 ('x') 和 GNU 长文件名 ('L') 只作用于紧跟着的下一项
+        let mut long_path = None;
+        let mut pax_size = None;
+        loop {
+            self.skip(self.remaining + self.padding)?;
+            self.remaining = 0;
+            self.padding = 0;
+            if self.done {
+                return Ok(None);
+            }
+            let header = match self.read_header()? {
+                Some(header) => header,
+                None => {
+                    self.done = true;
+                    return Ok(None);
+                }
+            };
+            // 链接, 设备和目录后面没有数据, 不管 size 里写的是什么
+            let size = match header.typeflag {
+                b'1'..=b'6' => 0,
+                _ => pax_size.take().unwrap_or(header.size),
+            };
+            self.padding = (BLOCK - size % BLOCK) % BLOCK;
+
+            let kind = match header.typeflag {
+                b'x' | b'L' => {
+                    let data = self.read_data(size)?;
+                    if header.typeflag == b'L' {
+                        let name = data.split(|&b| b == 0).next().unwrap_or_default();
+                        long_path = Some(String::from_utf8_lossy(name).into_owned());
+                    } else {
+                        for (key, value) in pax_records(&data)? {
+                            match key {
+                                "path" => long_path = Some(value.to_string()),
+                                "size" => pax_size = Some(parse_pax_size(value)?),
+                                _ => {}
+                            }
+                        }
+                    }
+                    continue;
+                }
+                // 全局的 pax 头里一般只有注释和时间, 用不上
+                b'g' => {
+                    self.remaining = size;
+                    continue;
+                }
+                b'0' | 0 | b'7' => EntryKind::File,
+                b'5' => EntryKind::Dir,
+                _ => EntryKind::Other,
+            };
+            self.remaining = size;
+            return Ok(Some(Entry {
+                path: long_path.take().unwrap_or(header.path),
+                kind,
+                reader: &mut self.reader,
+                remaining: &mut self.remaining,
+            }));
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<Option<Header>> {
+        let mut block = [0; BLOCK as usize];
+        if !read_full(&mut self.reader, &mut block)? || block.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+
+        // 校验和按把校验和字段本身当作 8 个空格来算
+        let expected = parse_number(&block[148..156])?;
+        let sum: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b })
+            .map(u64::from)
+            .sum();
+        if sum != expected {
+            return Err(invalid("tar header checksum mismatch"));
+        }
+
+        let name = field_str(&block[0..100]);
+        // ustar 把长路径拆成 prefix 和 name 两段
+        let path = if &block[257..262] == b"ustar" && block[345] != 0 {
+            format!("{}/{}", field_str(&block[345..500]), name)
+        } else {
+            name
+        };
+        Ok(Some(Header {
+            path,
+            size: parse_number(&block[124..136])?,
+            typeflag: block[156],
+        }))
+    }
+
+    fn read_data(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.reader).take(size).read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(data)
+    }
+
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(n), &mut io::sink())?;
+        if skipped < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Entry<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = (*self.remaining).min(buf.len() as u64) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        *self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// 搜索 tar 包里的每个普通文件, 输出时的路径是 `archive.tar:inner/path`
+///
+/// 某一项出错 (比如不是 UTF-8) 时把错误放进 skipped, 接着搜下一项, 和目录里的
+/// 文件一样. 包本身坏了 (头部读不出来) 找不到下一项在哪, 只能整个包报错
+pub fn search_archive<W: Write>(
+    matcher: &Matcher,
+    path: &Path,
+    options: &SearchOptions,
+    printer: &mut Printer<W>,
+    skipped: &mut Vec<MinigrepError>,
+) -> Result<Stats, MinigrepError> {
+    let read_error = |e| MinigrepError::io(path, e);
+    let file = File::open(path).map_err(read_error)?;
+    let mut archive = Archive::new(BufReader::new(file));
+    let mut total = Stats::default();
+    while let Some(entry) = archive.next_entry().map_err(read_error)? {
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+        let inner = PathBuf::from(format!("{}:{}", path.display(), entry.path));
+        let searched = encoding::decode_bom(Box::new(BufReader::new(entry)))
+            .map_err(|e| MinigrepError::io(&inner, e))
+            .and_then(|reader| search::search_reader(matcher, &inner, reader, options, printer));
+        match searched {
+            Ok(stats) => total.add(stats),
+            // 写标准输出失败时后面的项也不用搜了
+            Err(e) if e.is_output_error() => return Err(e),
+            Err(e) => skipped.push(e),
+        }
+    }
+    Ok(total)
+}
+
+// 返回 false 表示一开始就读到了结尾, 读到一半结束算错误
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+// 以 NUL 结尾 (或者填满) 的字符串字段
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// 数字字段一般是八进制文本; 放不下时 GNU 用最高位置 1 的 base-256 大端整数
+fn parse_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut n = u64::from(field[0] & 0x7f);
+        for &b in &field[1..] {
+            n = n
+                .checked_mul(256)
+                .and_then(|n| n.checked_add(u64::from(b)))
+                .ok_or_else(|| invalid("tar header number too large"))?;
+        }
+        return Ok(n);
+    }
+    let text = str::from_utf8(field).map_err(|_| invalid("invalid number in tar header"))?;
+    let text = text.trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid("invalid number in tar header"))
+}
+
+// pax 扩展头由 "长度 key=value\n" 组成, 长度包括它自己和换行
+fn pax_records(data: &[u8]) -> io::Result<Vec<(&str, &str)>> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() && rest[0] != 0 {
+        let space = rest
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or_else(|| invalid("invalid pax record"))?;
+        let len: usize = str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len > space + 1 && len <= rest.len())
+            .ok_or_else(|| invalid("invalid pax record length"))?;
+        let record = str::from_utf8(&rest[space + 1..len - 1])
+            .map_err(|_| invalid("pax record is not UTF-8"))?;
+        let (key, value) = record
+            .split_once('=')
+            .ok_or_else(|| invalid("invalid pax record"))?;
+        records.push((key, value));
+        rest = &rest[len..];
+    }
+    Ok(records)
+}
+
+fn parse_pax_size(value: &str) -> io::Result<u64> {
+    value.parse().map_err(|_| invalid("invalid pax size"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minigrep::output::PrintOptions;
+    use crate::MatchMode;
+    use std::io::Cursor;
+    use std::{env, fs, process};
+
+    // 按 ustar 格式拼一个头部块, 校验和最后再填
+    fn header(name: &str, size: usize, typeflag: u8) -> Vec<u8> {
+        let mut block = vec![0; 512];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[100..107].copy_from_slice(b"0000644");
+        block[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        block[156] = typeflag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[148..156].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+        block[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        block
+    }
+
+    fn entry(tar: &mut Vec<u8>, name: &str, data: &[u8], typeflag: u8) {
+        tar.extend(header(name, data.len(), typeflag));
+        tar.extend(data);
+        tar.resize(tar.len().div_ceil(512) * 512, 0);
+    }
+
+    fn pax(path: &str) -> Vec<u8> {
+        let record = format!(" path={}\n", path);
+        // 长度字段算上自己的位数
+        let digits = (record.len() + 3).to_string().len();
+        let len = record.len() + digits;
+        format!("{}{}", len, record).into_bytes()
+    }
+
+    #[test]
+    fn reads_entries() {
+        let long = format!("{}/notes.txt", "deep".repeat(30));
+        let mut tar = Vec::new();
+        entry(&mut tar, "poem/", b"", b'5');
+        entry(
+            &mut tar,
+            "poem/poem.txt",
+            b"I'm nobody! Who are you?\n",
+            b'0',
+        );
+        entry(&mut tar, "PaxHeaders/x", &pax(&long), b'x');
+        entry(&mut tar, "truncated", b"a pax path\n", b'0');
+        tar.extend([0; 1024]);
+
+        let mut archive = Archive::new(Cursor::new(tar));
+        let mut found = Vec::new();
+        while let Some(mut entry) = archive.next_entry().unwrap() {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            found.push((entry.path.clone(), entry.kind, contents));
+        }
+        assert_eq!(
+            vec![
+                ("poem/".to_string(), EntryKind::Dir, String::new()),
+                (
+                    "poem/poem.txt".to_string(),
+                    EntryKind::File,
+                    "I'm nobody! Who are you?\n".to_string()
+                ),
+                (long, EntryKind::File, "a pax path\n".to_string()),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn bad_entry_does_not_stop_the_archive() {
+        let mut tar = Vec::new();
+        entry(&mut tar, "bad.txt", b"caf\xe9 ok\n", b'0');
+        entry(&mut tar, "good.txt", b"ok\n", b'0');
+        tar.extend([0; 1024]);
+        let path = env::temp_dir().join(format!("minigrep-tar-{}.tar", process::id()));
+        fs::write(&path, tar).unwrap();
+
+        let matcher = Matcher::with_patterns(&["ok"], MatchMode::Literal, true).unwrap();
+        let options = PrintOptions {
+            with_filename: true,
+            ..Default::default()
+        };
+        let mut printer = Printer::new(Vec::new(), options);
+        let mut skipped = Vec::new();
+        let stats = search_archive(
+            &matcher,
+            &path,
+            &SearchOptions::default(),
+            &mut printer,
+            &mut skipped,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(1, stats.matched_lines);
+        assert_eq!(
+            format!("{}:good.txt:ok\n", path.display()),
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+        match &skipped[..] {
+            [MinigrepError::Encoding { path: bad, line: 1 }] => {
+                assert_eq!(
+                    format!("{}:bad.txt", path.display()),
+                    bad.display().to_string()
+                )
+            }
+            _ => panic!("expected one encoding error, got {:?}", skipped),
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_headers() {
+        let mut tar = header("a.txt", 3, b'0');
+        tar[0] = b'b';
+        assert!(Archive::new(Cursor::new(tar)).next_entry().is_err());
+        assert_eq!(
+            1 << 40,
+            parse_number(&[0x80, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]).unwrap()
+        );
+        assert!(is_archive(Path::new("release.TAR")));
+        assert!(!is_archive(Path::new("release.tar.gz")));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::minigrep::error::MinigrepError;
+use crate::minigrep::ignore::{self, Gitignore};
+use glob::{Pattern, PatternError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 递归搜索时决定哪些文件要搜: --include / --exclude 的 glob, 隐藏文件和忽略文件
+pub struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    // 是否进入以 `.` 开头的文件和目录
+    hidden: bool,
+    // 是否遵守 .gitignore / .ignore
+    ignore_files: bool,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<FileFilter, PatternError> {
+        let compile = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|g| Pattern::new(g))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(FileFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            hidden: false,
+            ignore_files: true,
+        })
+    }
+
+    /// --hidden: 也搜索隐藏文件
+    pub fn hidden(mut self, yes: bool) -> FileFilter {
+        self.hidden = yes;
+        self
+    }
+
+    /// --no-ignore 时传 false
+    pub fn ignore_files(mut self, yes: bool) -> FileFilter {
+        self.ignore_files = yes;
+        self
+    }
+
+    // 文件名 或者 完整路径 命中任意一个 glob 就算匹配
+    fn matches_any(patterns: &[Pattern], path: &Path) -> bool {
+        let name = path.file_name().map(Path::new);
+        patterns
+            .iter()
+            .any(|p| p.matches_path(path) || name.is_some_and(|n| p.matches_path(n)))
+    }
+
+    /// 被排除的目录整棵子树都不会再进入
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        FileFilter::matches_any(&self.exclude, path)
+    }
+
+    /// 没有 --include 时所有文件都算包含
+    pub fn is_included(&self, path: &Path) -> bool {
+        self.include.is_empty() || FileFilter::matches_any(&self.include, path)
+    }
+}
+
+/// 把命令行上的文件和目录展开成要搜索的文件列表
+///
+/// 命令行上直接写出的文件和目录总是会被搜索, 目录会递归展开,
+/// 展开时按文件名排序保证输出顺序稳定, 符号链接不跟随以免出现环.
+/// 展开时跳过隐藏文件, 以及被 .gitignore / .ignore 忽略的路径 (还有 .git 目录),
+/// 忽略文件除了沿途目录里的, 还有从所在 git 仓库顶层到这个目录之间的.
+///
+/// 读不了的目录或者忽略文件放进 skipped, 其余的照常展开
+pub fn collect_files(
+    paths: &[PathBuf],
+    filter: &FileFilter,
+    skipped: &mut Vec<MinigrepError>,
+) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut ignores = Vec::new();
+        if filter.ignore_files {
+            match ignore::for_ancestors(path) {
+                Ok(ancestors) => ignores = ancestors,
+                Err(e) => skipped.push(MinigrepError::io(path, e)),
+            }
+        }
+        walk_dir(path, filter, &mut ignores, &mut files, skipped);
+    }
+    files
+}
+
+// ignores 是从根目录到 dir 一路上读到的忽略文件, 离开 dir 时把这一层的弹出去
+fn walk_dir(
+    dir: &Path,
+    filter: &FileFilter,
+    ignores: &mut Vec<Gitignore>,
+    files: &mut Vec<PathBuf>,
+    skipped: &mut Vec<MinigrepError>,
+) {
+    // 目录本身读不了时只报这一个错, 不用再去读里面的忽略文件
+    let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>());
+    let mut entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => return skipped.push(MinigrepError::io(dir, e)),
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let depth = ignores.len();
+    if filter.ignore_files {
+        for name in ignore::IGNORE_FILES {
+            match Gitignore::from_dir(dir, name) {
+                Ok(Some(gitignore)) => ignores.push(gitignore),
+                Ok(None) => {}
+                Err(e) => skipped.push(MinigrepError::io(dir.join(name), e)),
+            }
+        }
+    }
+
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name();
+        if !filter.hidden && name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if filter.is_excluded(&path) {
+            continue;
+        }
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                skipped.push(MinigrepError::io(&path, e));
+                continue;
+            }
+        };
+        let is_dir = file_type.is_dir();
+        if filter.ignore_files
+            && (is_dir && name == ".git" || ignore::is_ignored(ignores, &path, is_dir))
+        {
+            continue;
+        }
+        if is_dir {
+            walk_dir(&path, filter, ignores, files, skipped);
+        } else if file_type.is_file() && filter.is_included(&path) {
+            files.push(path);
+        }
+    }
+    ignores.truncate(depth);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // 在临时目录下搭一棵小的目录树
+    fn fixture(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("minigrep-walk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("README.md"), "readme").unwrap();
+        fs::write(root.join("src/lib.rs"), "lib").unwrap();
+        fs::write(root.join("src/nested/mod.rs"), "mod").unwrap();
+        fs::write(root.join("target/out.rs"), "out").unwrap();
+        root
+    }
+
+    fn relative(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| {
+                f.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_recursively_in_order() {
+        let root = fixture("all");
+        let filter = FileFilter::new(&[], &[]).unwrap();
+        let files = collect_files(&[root.clone()], &filter, &mut Vec::new());
+        assert_eq!(
+            vec![
+                "README.md",
+                "src/lib.rs",
+                "src/nested/mod.rs",
+                "target/out.rs"
+            ],
+            relative(&root, files)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let root = fixture("filter");
+        let filter = FileFilter::new(&["*.rs".to_string()], &["target".to_string()]).unwrap();
+        let files = collect_files(&[root.clone()], &filter, &mut Vec::new());
+        assert_eq!(
+            vec!["src/lib.rs", "src/nested/mod.rs"],
+            relative(&root, files)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignore_files_and_hidden() {
+        let root = fixture("ignore");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".git/config"), "git").unwrap();
+        fs::write(root.join(".env"), "secret").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.md\n").unwrap();
+        fs::write(root.join("src/NOTES.md"), "notes").unwrap();
+        // 更深的目录里可以重新包含, .ignore 又比同目录的 .gitignore 优先
+        fs::write(root.join("src/.gitignore"), "!*.md\n").unwrap();
+        fs::write(root.join("src/nested/.ignore"), "mod.rs\n").unwrap();
+
+        let filter = FileFilter::new(&[], &[]).unwrap();
+        let files = collect_files(&[root.clone()], &filter, &mut Vec::new());
+        assert_eq!(vec!["src/NOTES.md", "src/lib.rs"], relative(&root, files));
+
+        let filter = FileFilter::new(&[], &[])
+            .unwrap()
+            .hidden(true)
+            .ignore_files(false);
+        let files = collect_files(&[root.clone()], &filter, &mut Vec::new());
+        assert_eq!(10, files.len());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_dir_is_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = fixture("unreadable");
+        let locked = root.join("src/nested");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // root 用户不受权限限制, 这时没法测
+        if fs::read_dir(&locked).is_err() {
+            let filter = FileFilter::new(&[], &[]).unwrap();
+            let mut skipped = Vec::new();
+            let files = collect_files(&[root.clone()], &filter, &mut skipped);
+            assert_eq!(
+                vec!["README.md", "src/lib.rs", "target/out.rs"],
+                relative(&root, files)
+            );
+            assert!(
+                matches!(&skipped[..], [MinigrepError::Io { path: Some(path), .. }] if *path == locked)
+            );
+        }
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,13 +1,250 @@
-pub struct ThreadPool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 固定数量的工作线程, 任务通过通道分发给空闲的线程
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>,
+    // 已经交给线程池还没执行完的任务数, 包括正在执行的和排队的
+    in_flight: Arc<AtomicUsize>,
+    // try_execute 允许的 in_flight 上限: 线程数加上排队的长度
+    limit: usize,
+}
+
+/// 线程池忙不忙, 可以交给任务自己去看, 不用借用 ThreadPool
+#[derive(Clone)]
+pub struct Load {
+    in_flight: Arc<AtomicUsize>,
+    size: usize,
+}
+
+impl Load {
+    /// 在排队等空闲线程的任务数
+    pub fn queued(&self) -> usize {
+        self.in_flight
+            .load(Ordering::SeqCst)
+            .saturating_sub(self.size)
+    }
+}
 
 impl ThreadPool {
-    // pub fn new(size: unsize) -> ThreadPool {
-    //     ThreadPool
-    // }
-    //
-    // pub fn execute<F>(&self, f: F)
-    // where
-    //     F: FnOnce() + Send + 'static,
-    // {
-    // }
+    /// 创建线程池
+    ///
+    /// size 为池中线程的数量
+    ///
+    /// # Panics
+    ///
+    /// `new` 函数在 size 为 0 时会 panic
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_queue(size, usize::MAX - size)
+    }
+
+    /// 创建排队长度有限的线程池
+    ///
+    /// 所有线程都在忙并且已经有 queue 个任务在排队时, try_execute 会拒绝新任务.
+    /// execute 不受这个限制
+    ///
+    /// # Panics
+    ///
+    /// size 为 0 时会 panic
+    pub fn with_queue(size: usize, queue: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel();
+        // 所有 worker 共享同一个接收端, 用 Mutex 保证一个任务只被一个 worker 取走
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..size)
+            .map(|id| Worker::new(id, Arc::clone(&receiver), Arc::clone(&in_flight)))
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+            in_flight,
+            limit: size.saturating_add(queue),
+        }
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.send(f);
+    }
+
+    /// 线程池还有空位时和 execute 一样, 否则不执行, 把 f 原样还给调用方
+    ///
+    /// 调用方可以拿回 f 里的资源做别的处理, 比如给连接回一个 503
+    pub fn try_execute<F>(&self, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let limit = self.limit;
+        let reserved = self
+            .in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < limit {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            });
+        match reserved {
+            Ok(_) => {
+                self.send(f);
+                Ok(())
+            }
+            Err(_) => Err(f),
+        }
+    }
+
+    /// 长时间运行的任务 (比如长连接) 可以用它看有没有别的任务在等, 早点把线程让出来
+    pub fn load(&self) -> Load {
+        Load {
+            in_flight: Arc::clone(&self.in_flight),
+            size: self.workers.len(),
+        }
+    }
+
+    fn send<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+// 先关掉发送端让 worker 的 recv 返回 Err 退出循环, 再等所有线程结束
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                // 任务 panic 时这个线程已经退出, panic 信息也打印过了,
+                // drop 里不要再跟着 panic, 记一下就好
+                let name = thread.thread().name().unwrap_or("worker").to_string();
+                if thread.join().is_err() {
+                    eprintln!("{} exited because a job panicked", name);
+                }
+            }
+        }
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        in_flight: Arc<AtomicUsize>,
+    ) -> Worker {
+        // 线程名里带上编号, panic 信息里能看出是哪个 worker
+        let thread = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || loop {
+                // 取到任务后锁马上释放, 执行任务时其他 worker 可以继续取
+                let message = receiver.lock().unwrap().recv();
+
+                match message {
+                    Ok(job) => {
+                        // 任务 panic 时也要把名额还回去
+                        let _done = Done(&in_flight);
+                        job();
+                    }
+                    Err(_) => break,
+                }
+            })
+            .unwrap();
+
+        Worker {
+            thread: Some(thread),
+        }
+    }
+}
+
+// 离开作用域时 in_flight 减一
+struct Done<'a>(&'a AtomicUsize);
+
+impl Drop for Done<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn runs_every_job_before_drop_returns() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPool::new(4);
+            for _ in 0..32 {
+                let counter = Arc::clone(&counter);
+                pool.execute(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        }
+        assert_eq!(32, counter.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn try_execute_rejects_when_queue_is_full() {
+        let pool = ThreadPool::with_queue(1, 1);
+        let (started_tx, started) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let blocking = move || {
+            started_tx.send(()).unwrap();
+            release_rx.lock().unwrap().recv().unwrap();
+        };
+        assert!(pool.try_execute(blocking).is_ok());
+        started.recv().unwrap();
+        let load = pool.load();
+        assert_eq!(0, load.queued());
+        // 唯一的线程在忙, 第一个排队成功, 第二个被退回
+        assert!(pool.try_execute(|| {}).is_ok());
+        assert!(pool.try_execute(|| {}).is_err());
+        assert_eq!(1, load.queued());
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn drop_survives_panicking_job() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPool::new(2);
+            pool.execute(|| panic!("job failed"));
+            for _ in 0..8 {
+                let counter = Arc::clone(&counter);
+                pool.execute(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        }
+        // 剩下的 worker 把其他任务都做完了
+        assert_eq!(8, counter.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_threads() {
+        ThreadPool::new(0);
+    }
 }